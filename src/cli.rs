@@ -0,0 +1,567 @@
+// The native `sift` tool (src/bin/sift.rs). It runs the same pipeline as the
+// wasm exports, but on image files and with errors reported as `SiftError`s
+// instead of `JsError`s, which can only be built inside a JS host.
+
+use crate::colmap;
+use crate::config::{MatchAlgorithm, MatchConfig, RansacConfig, SiftConfig};
+use crate::decode::{decode_gray, GrayImage};
+use crate::epipolar::estimate_fundamental;
+use crate::feature_io;
+use crate::homography::estimate_homography;
+use crate::linalg::Mat3;
+use crate::match_keypoints::match_descriptors_topk_impl;
+use crate::ransac::matched_points;
+use crate::{run_sift, SiftResult, KEYPOINT_STRIDE};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+// Length of the descriptors written and matched by the tool
+const DESCRIPTOR_LEN: usize = 128;
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+/// SIFT feature detection and matching on PNG, JPEG and PGM images.
+#[derive(Parser)]
+#[command(name = "sift", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Detect keypoints and write them with their descriptors to a file.
+    ///
+    /// The default `text` format has a `<n> <d>` header line, then one line
+    /// per keypoint with `x y octave level sigma angle` followed by the `d`
+    /// descriptor values.
+    Detect {
+        image: PathBuf,
+        /// Output file [default: the image path with the format's extension]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Output format; `vlfeat` writes a `.frame` and a `.descr` file
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+        #[command(flatten)]
+        detect: DetectArgs,
+    },
+    /// Match the features of two images and print match statistics.
+    Match {
+        a: PathBuf,
+        b: PathBuf,
+        /// Write the matches as `i j` keypoint index pairs, one per line
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        detect: DetectArgs,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Match two images and verify the matches geometrically with RANSAC.
+    Verify {
+        a: PathBuf,
+        b: PathBuf,
+        /// Write the inlier matches as `i j` keypoint index pairs
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Write the inlier matches as a COLMAP match list for
+        /// `matches_importer --match_type inliers`, named by the image file names
+        #[arg(long)]
+        colmap: Option<PathBuf>,
+        /// Geometric model fitted to the matches
+        #[arg(long, value_enum, default_value_t = Model::Homography)]
+        model: Model,
+        /// Inlier distance in pixels
+        #[arg(long, default_value_t = 3.0)]
+        threshold: f32,
+        /// RANSAC confidence, controls the adaptive iteration count
+        #[arg(long, default_value_t = 0.995)]
+        confidence: f32,
+        #[arg(long, default_value_t = 2000)]
+        max_iterations: usize,
+        /// Seed of the RANSAC sampling
+        #[arg(long, default_value_t = 0)]
+        seed: u32,
+        #[command(flatten)]
+        detect: DetectArgs,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Plain text with f32 descriptors (`.sift`)
+    Text,
+    /// Lowe's `.key` format
+    Key,
+    /// VLFeat's `.frame` / `.descr` pair
+    Vlfeat,
+    /// Versioned binary format, exact (`.siftb`)
+    Binary,
+    /// COLMAP feature file, `x y scale orientation` (`<image>.txt`)
+    Colmap,
+    /// COLMAP feature file with the 6-column affine shape
+    ColmapAffine,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "sift",
+            Format::Key => "key",
+            Format::Vlfeat => "frame",
+            Format::Binary => "siftb",
+            Format::Colmap | Format::ColmapAffine => "txt",
+        }
+    }
+
+    // COLMAP looks for `<image name>.txt`, i.e. appends rather than replaces
+    fn default_path(self, image: &Path) -> PathBuf {
+        match self {
+            Format::Colmap | Format::ColmapAffine => {
+                let mut path = image.as_os_str().to_owned();
+                path.push(".txt");
+                path.into()
+            }
+            _ => image.with_extension(self.extension()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Model {
+    /// Planar scene or pure rotation
+    Homography,
+    /// General two-view geometry
+    Fundamental,
+}
+
+#[derive(Args)]
+struct DetectArgs {
+    /// Scales per octave
+    #[arg(long, default_value_t = 3)]
+    scales: usize,
+    /// DoG contrast threshold
+    #[arg(long, default_value_t = 0.03)]
+    contrast_thresh: f32,
+    /// Edge response ratio
+    #[arg(long, default_value_t = 10.0)]
+    edge_r: f32,
+    /// Add a doubled-resolution first octave
+    #[arg(long)]
+    upsample: bool,
+    /// Keep only the N strongest keypoints
+    #[arg(long)]
+    max_features: Option<usize>,
+    /// Limit the number of octaves
+    #[arg(long)]
+    max_octaves: Option<usize>,
+}
+
+impl DetectArgs {
+    fn config(&self) -> SiftConfig {
+        let mut config = SiftConfig::default();
+        config.set_scales(self.scales);
+        config.set_contrast_thresh(self.contrast_thresh);
+        config.set_edge_r(self.edge_r);
+        config.set_upsample(self.upsample);
+        config.set_max_features(self.max_features);
+        config.set_max_octaves(self.max_octaves);
+        config
+    }
+}
+
+#[derive(Args)]
+struct MatchArgs {
+    /// Lowe's ratio test threshold
+    #[arg(long, default_value_t = 0.75)]
+    ratio: f32,
+    /// Skip the mutual nearest-neighbour check
+    #[arg(long)]
+    no_cross_check: bool,
+    /// Keep only the K best matches
+    #[arg(long, default_value_t = usize::MAX, hide_default_value = true)]
+    top_k: usize,
+    /// Approximate matching with a randomised kd-forest
+    #[arg(long)]
+    kd_forest: bool,
+}
+
+impl MatchArgs {
+    fn config(&self) -> MatchConfig {
+        let mut config = MatchConfig::default();
+        if self.kd_forest {
+            config.set_algorithm(MatchAlgorithm::KdForest);
+        }
+        config
+    }
+}
+
+/// Entry point of the `sift` binary.
+pub fn main() -> ExitCode {
+    match run(&Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sift: error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> CliResult<()> {
+    match &cli.command {
+        Command::Detect {
+            image,
+            output,
+            format,
+            detect,
+        } => run_detect_command(image, output.as_deref(), *format, detect),
+        Command::Match {
+            a,
+            b,
+            output,
+            detect,
+            matching,
+        } => run_match_command(a, b, output.as_deref(), detect, matching).map(|_| ()),
+        Command::Verify {
+            a,
+            b,
+            output,
+            colmap,
+            model,
+            threshold,
+            confidence,
+            max_iterations,
+            seed,
+            detect,
+            matching,
+        } => {
+            let mut ransac = RansacConfig::default();
+            ransac.set_reproj_threshold(*threshold);
+            ransac.set_confidence(*confidence);
+            ransac.set_max_iterations(*max_iterations);
+            ransac.set_seed(*seed);
+            let outputs = VerifyOutputs {
+                pairs: output.as_deref(),
+                colmap: colmap.as_deref(),
+            };
+            run_verify_command(a, b, outputs, *model, &ransac, detect, matching)
+        }
+    }
+}
+
+fn with_path<E: std::fmt::Display>(path: &Path) -> impl FnOnce(E) -> Box<dyn Error> + '_ {
+    move |e| format!("{}: {}", path.display(), e).into()
+}
+
+fn load_image(path: &Path) -> CliResult<GrayImage> {
+    let bytes = std::fs::read(path).map_err(with_path(path))?;
+    decode_gray(&bytes).map_err(with_path(path))
+}
+
+// Decodes and runs detection plus description on one image
+fn extract(path: &Path, config: &SiftConfig) -> CliResult<SiftResult> {
+    let image = load_image(path)?;
+    let start = Instant::now();
+    let result = run_sift(&image.data, image.width, image.height, config, None).map_err(with_path(path))?;
+    println!(
+        "{}: {}x{}, {} keypoints in {:.1} ms",
+        path.display(),
+        image.width,
+        image.height,
+        result.kps.len(),
+        start.elapsed().as_secs_f64() * 1e3
+    );
+    Ok(result)
+}
+
+fn write_text_features(path: &Path, result: &SiftResult) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let n = result.kps.len();
+    writeln!(out, "{} {}", n, DESCRIPTOR_LEN)?;
+    for (kp, desc) in result
+        .flat_keypoints()
+        .chunks_exact(KEYPOINT_STRIDE)
+        .zip(result.descriptors.chunks_exact(DESCRIPTOR_LEN))
+    {
+        write!(out, "{} {} {} {} {} {}", kp[0], kp[1], kp[2], kp[3], kp[4], kp[5])?;
+        for v in desc {
+            write!(out, " {}", v)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+fn write_pairs(path: &Path, pairs: impl Iterator<Item = (u32, u32)>) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for (i, j) in pairs {
+        writeln!(out, "{} {}", i, j)?;
+    }
+    out.flush()
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> CliResult<()> {
+    std::fs::write(path, contents).map_err(with_path(path))?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+fn run_detect_command(image: &Path, output: Option<&Path>, format: Format, detect: &DetectArgs) -> CliResult<()> {
+    let result = extract(image, &detect.config())?;
+    let output = output.map_or_else(|| format.default_path(image), Path::to_path_buf);
+    let kps = &result.kps;
+    match format {
+        Format::Text => {
+            write_text_features(&output, &result).map_err(with_path(&output))?;
+            println!("wrote {}", output.display());
+        }
+        Format::Key => write_file(&output, feature_io::write_lowe_key(kps, &result.descriptors)?.as_bytes())?,
+        Format::Vlfeat => {
+            let (frames, descriptors) = feature_io::write_vlfeat(kps, &result.descriptors)?;
+            write_file(&output.with_extension("frame"), frames.as_bytes())?;
+            write_file(&output.with_extension("descr"), descriptors.as_bytes())?;
+        }
+        Format::Binary => write_file(&output, &feature_io::write_binary(kps, &result.descriptors)?)?,
+        Format::Colmap | Format::ColmapAffine => {
+            let affine = matches!(format, Format::ColmapAffine);
+            write_file(&output, colmap::write_features(kps, &result.descriptors, affine)?.as_bytes())?
+        }
+    }
+    Ok(())
+}
+
+// Features of both images and the [i1, j1, i2, j2, ...] matches between them
+struct Matched {
+    a: SiftResult,
+    b: SiftResult,
+    matches: Vec<u32>,
+}
+
+fn run_match_command(
+    a: &Path,
+    b: &Path,
+    output: Option<&Path>,
+    detect: &DetectArgs,
+    matching: &MatchArgs,
+) -> CliResult<Matched> {
+    let config = detect.config();
+    let fa = extract(a, &config)?;
+    let fb = extract(b, &config)?;
+
+    let start = Instant::now();
+    let matches = match_descriptors_topk_impl(
+        &fa.descriptors,
+        &fb.descriptors,
+        DESCRIPTOR_LEN,
+        matching.ratio,
+        !matching.no_cross_check,
+        matching.top_k,
+        &matching.config(),
+    )?;
+    let elapsed = start.elapsed().as_secs_f64() * 1e3;
+
+    let n = matches.len() / 2;
+    let distances: Vec<f32> = matches
+        .chunks_exact(2)
+        .map(|p| {
+            let (i, j) = (p[0] as usize, p[1] as usize);
+            crate::simd::l2_sq_f32(
+                &fa.descriptors[i * DESCRIPTOR_LEN..(i + 1) * DESCRIPTOR_LEN],
+                &fb.descriptors[j * DESCRIPTOR_LEN..(j + 1) * DESCRIPTOR_LEN],
+            )
+            .sqrt()
+        })
+        .collect();
+    let smaller = fa.kps.len().min(fb.kps.len()).max(1);
+    println!(
+        "{} matches in {:.1} ms ({:.1}% of the smaller set)",
+        n,
+        elapsed,
+        100.0 * n as f64 / smaller as f64
+    );
+    if n > 0 {
+        let mean = distances.iter().sum::<f32>() / n as f32;
+        let max = distances.iter().fold(0.0f32, |m, &d| m.max(d));
+        println!("descriptor distance: mean {:.4}, max {:.4}", mean, max);
+    }
+
+    if let Some(output) = output {
+        let pairs = matches.chunks_exact(2).map(|p| (p[0], p[1]));
+        write_pairs(output, pairs).map_err(with_path(output))?;
+        println!("wrote {}", output.display());
+    }
+    Ok(Matched { a: fa, b: fb, matches })
+}
+
+fn print_matrix(name: &str, m: &Mat3) {
+    println!("{}:", name);
+    for row in m {
+        println!("  {:>14.6e} {:>14.6e} {:>14.6e}", row[0], row[1], row[2]);
+    }
+}
+
+// Files written by `verify`
+struct VerifyOutputs<'a> {
+    pairs: Option<&'a Path>,
+    colmap: Option<&'a Path>,
+}
+
+fn run_verify_command(
+    a: &Path,
+    b: &Path,
+    outputs: VerifyOutputs,
+    model: Model,
+    ransac: &RansacConfig,
+    detect: &DetectArgs,
+    matching: &MatchArgs,
+) -> CliResult<()> {
+    let Matched { a: fa, b: fb, matches } = run_match_command(a, b, None, detect, matching)?;
+    let (src, dst) = matched_points(fa.flat_keypoints(), fb.flat_keypoints(), &matches)?;
+
+    let start = Instant::now();
+    let inliers = match model {
+        Model::Homography => {
+            let h = estimate_homography(&src, &dst, ransac)?;
+            print_matrix("homography", &h.matrix);
+            h.inliers
+        }
+        Model::Fundamental => {
+            let f = estimate_fundamental(&src, &dst, ransac)?;
+            print_matrix("fundamental matrix", &f.matrix);
+            let (sum, count) = f
+                .inliers
+                .iter()
+                .zip(&f.sampson_errors)
+                .filter(|(&inlier, _)| inlier)
+                .fold((0.0f64, 0usize), |(s, c), (_, &e)| (s + e as f64, c + 1));
+            if count > 0 {
+                println!("mean inlier Sampson error: {:.3} px", sum / count as f64);
+            }
+            f.inliers
+        }
+    };
+    let num_inliers = inliers.iter().filter(|&&m| m).count();
+    println!(
+        "{} / {} inliers ({:.1}%) in {:.1} ms",
+        num_inliers,
+        inliers.len(),
+        100.0 * num_inliers as f64 / inliers.len().max(1) as f64,
+        start.elapsed().as_secs_f64() * 1e3
+    );
+
+    if let Some(output) = outputs.pairs {
+        let pairs = matches
+            .chunks_exact(2)
+            .zip(&inliers)
+            .filter(|(_, &inlier)| inlier)
+            .map(|(p, _)| (p[0], p[1]));
+        write_pairs(output, pairs).map_err(with_path(output))?;
+        println!("wrote {}", output.display());
+    }
+    if let Some(output) = outputs.colmap {
+        let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let flags: Vec<u8> = inliers.iter().map(|&m| m as u8).collect();
+        write_file(output, colmap::write_match_block(&name(a), &name(b), &matches, &flags)?.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const WIDTH: usize = 240;
+    const HEIGHT: usize = 180;
+    // image b is image a moved right and down by this many pixels
+    const SHIFT: (usize, usize) = (7, 4);
+
+    // 8-bit PGM of overlapping random rectangles, shifted by `shift`
+    fn rectangles_pgm(shift: (usize, usize)) -> Vec<u8> {
+        let mut rng = Rng::new(3);
+        let mut image = vec![128u8; WIDTH * HEIGHT];
+        for _ in 0..50 {
+            let (x0, y0) = (shift.0 + rng.below(WIDTH), shift.1 + rng.below(HEIGHT));
+            let (w, h) = (8 + rng.below(40), 8 + rng.below(40));
+            let value = rng.below(256) as u8;
+            for y in y0.min(HEIGHT)..(y0 + h).min(HEIGHT) {
+                image[y * WIDTH + x0.min(WIDTH)..y * WIDTH + (x0 + w).min(WIDTH)].fill(value);
+            }
+        }
+        let mut pgm = format!("P5\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+        pgm.extend_from_slice(&image);
+        pgm
+    }
+
+    fn sift(args: &[&Path]) {
+        let args = std::iter::once("sift".as_ref()).chain(args.iter().map(|p| p.as_os_str()));
+        run(&Cli::try_parse_from(args).unwrap()).unwrap();
+    }
+
+    fn read_text(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn detect_match_and_verify_write_every_format() {
+        let dir = std::env::temp_dir().join(format!("sift-cli-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.pgm"), dir.join("b.pgm"));
+        std::fs::write(&a, rectangles_pgm((0, 0))).unwrap();
+        std::fs::write(&b, rectangles_pgm(SHIFT)).unwrap();
+        let arg = |s: &'static str| Path::new(s);
+
+        // detect: every format, on the default paths
+        for format in ["text", "key", "vlfeat", "binary", "colmap", "colmap-affine"] {
+            sift(&[arg("detect"), &a, arg("--format"), arg(format)]);
+        }
+        sift(&[arg("detect"), &b, arg("--format"), arg("binary")]);
+        let (kps_a, desc_a) = feature_io::read_binary(&std::fs::read(dir.join("a.siftb")).unwrap()).unwrap();
+        let (kps_b, _) = feature_io::read_binary(&std::fs::read(dir.join("b.siftb")).unwrap()).unwrap();
+        let n = kps_a.len();
+        assert!(n >= 30, "{} keypoints", n);
+        assert_eq!(desc_a.len(), n * DESCRIPTOR_LEN);
+
+        let header = format!("{} {}", n, DESCRIPTOR_LEN);
+        let text = read_text(&dir.join("a.sift"));
+        assert_eq!(text.lines().next(), Some(header.as_str()));
+        assert_eq!(text.lines().nth(1).unwrap().split_whitespace().count(), 6 + DESCRIPTOR_LEN);
+        let (key_kps, _) = feature_io::read_lowe_key(&read_text(&dir.join("a.key")), &SiftConfig::default()).unwrap();
+        assert_eq!(key_kps.len(), n);
+        assert_eq!(read_text(&dir.join("a.frame")).lines().count(), n);
+        assert_eq!(read_text(&dir.join("a.descr")).lines().count(), n);
+        // both COLMAP forms go to a.pgm.txt; the affine one was written last
+        let colmap = read_text(&dir.join("a.pgm.txt"));
+        assert_eq!(colmap.lines().next(), Some(header.as_str()));
+        assert_eq!(colmap.lines().nth(1).unwrap().split_whitespace().count(), 6 + DESCRIPTOR_LEN);
+
+        // match: `i j` pairs
+        let matches_path = dir.join("matches.txt");
+        sift(&[arg("match"), &a, &b, arg("--output"), &matches_path]);
+        let matches = read_text(&matches_path).lines().count();
+        assert!(matches >= 20, "{} matches", matches);
+
+        // verify: the inlier pairs follow the shift between the images
+        let (inliers_path, colmap_path) = (dir.join("inliers.txt"), dir.join("colmap_matches.txt"));
+        sift(&[arg("verify"), &a, &b, arg("--output"), &inliers_path, arg("--colmap"), &colmap_path]);
+        let inliers = read_text(&inliers_path);
+        assert!(inliers.lines().count() >= 20);
+        for line in inliers.lines() {
+            let ij: Vec<usize> = line.split_whitespace().map(|v| v.parse().unwrap()).collect();
+            let (p, q) = (&kps_a[ij[0]], &kps_b[ij[1]]);
+            assert!((q.x() - p.x() - SHIFT.0 as f32).abs() < 3.0 && (q.y() - p.y() - SHIFT.1 as f32).abs() < 3.0);
+        }
+        let colmap_matches = read_text(&colmap_path);
+        assert_eq!(colmap_matches, format!("a.pgm b.pgm\n{}\n", inliers));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+// Export to COLMAP's text import formats.
+//
+// Features (`feature_importer`, one `<image name>.txt` per image): a
+// `<n> 128` header, then per keypoint its geometry followed by the 128
+// descriptor values as integers 0..=255. The geometry is either the 4-column
+// `x y scale orientation` form or the 6-column affine form `x y a11 a12 a21
+// a22`, with [a11 a12; a21 a22] = scale * [cos -sin; sin cos](orientation)
+// as COLMAP derives it itself. COLMAP puts the centre of the top-left pixel
+// at (0.5, 0.5), so positions are shifted by half a pixel. Orientations follow
+// VLFeat's convention, which is ours, but descriptors are stored with Lowe's
+// orientation bin order, as COLMAP converts its own VLFeat descriptors.
+//
+// Matches (`matches_importer`): per image pair a line with the two image
+// names, one `i j` line per match and a blank line. Imported with
+// `--match_type inliers`, the pairs are stored as verified two-view geometry.
+
+use crate::error::SiftError;
+use crate::feature_io::lowe_descriptor_order;
+use crate::keypoints::{quantize_descriptors, Keypoint};
+use std::fmt::Write;
+
+const COLMAP_DESCRIPTOR_LEN: usize = 128;
+
+pub fn write_features(kps: &[Keypoint], desc: &[f32], affine: bool) -> Result<String, SiftError> {
+    let expected = kps.len() * COLMAP_DESCRIPTOR_LEN;
+    if desc.len() != expected {
+        return Err(SiftError::BufferLength {
+            name: "descriptors",
+            expected,
+            actual: desc.len(),
+        });
+    }
+    let quantized = quantize_descriptors(&lowe_descriptor_order(desc));
+    let mut out = String::new();
+    // writing to a String cannot fail
+    let _ = writeln!(out, "{} {}", kps.len(), COLMAP_DESCRIPTOR_LEN);
+    for (kp, d) in kps.iter().zip(quantized.chunks_exact(COLMAP_DESCRIPTOR_LEN)) {
+        let (x, y) = (kp.x() + 0.5, kp.y() + 0.5);
+        if affine {
+            let (sin, cos) = kp.angle().sin_cos();
+            let s = kp.sigma();
+            let _ = write!(out, "{} {} {} {} {} {}", x, y, s * cos, -s * sin, s * sin, s * cos);
+        } else {
+            let _ = write!(out, "{} {} {} {}", x, y, kp.sigma(), kp.angle());
+        }
+        for v in d {
+            let _ = write!(out, " {}", v);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn check_image_name(name: &'static str, value: &str) -> Result<(), SiftError> {
+    // the importer splits the pair line at spaces
+    if value.is_empty() || value.chars().any(char::is_whitespace) {
+        return Err(SiftError::invalid_parameter(
+            name,
+            format!("{:?} must be non-empty and contain no whitespace", value),
+        ));
+    }
+    Ok(())
+}
+
+// One image pair of a match list. `matches` holds [i1, j1, i2, j2, ...]
+// pairs as returned by `match_descriptors_topk`; `inliers`, if not empty,
+// has one flag per pair and only the non-zero ones are written.
+pub fn write_match_block(image1: &str, image2: &str, matches: &[u32], inliers: &[u8]) -> Result<String, SiftError> {
+    check_image_name("image1", image1)?;
+    check_image_name("image2", image2)?;
+    if !matches.len().is_multiple_of(2) {
+        return Err(SiftError::invalid_parameter("matches", "length must be even (index pairs)"));
+    }
+    if !inliers.is_empty() && inliers.len() != matches.len() / 2 {
+        return Err(SiftError::BufferLength {
+            name: "inliers",
+            expected: matches.len() / 2,
+            actual: inliers.len(),
+        });
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{} {}", image1, image2);
+    for (k, pair) in matches.chunks_exact(2).enumerate() {
+        if inliers.get(k).is_none_or(|&flag| flag != 0) {
+            let _ = writeln!(out, "{} {}", pair[0], pair[1]);
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    // values of one feature line of `write_features`
+    fn feature_line(text: &str) -> Vec<f32> {
+        let line = text.lines().nth(1).unwrap();
+        line.split_whitespace().map(|v| v.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn features_are_shifted_by_half_a_pixel() {
+        let kp = Keypoint::new(10.0, 20.0, 2.0, 0.25, 0, 1, 0.0);
+        let desc = vec![0.0; COLMAP_DESCRIPTOR_LEN];
+        let text = write_features(&[kp], &desc, false).unwrap();
+        assert_eq!(text.lines().next(), Some("1 128"));
+        let values = feature_line(&text);
+        assert_eq!(values.len(), 4 + COLMAP_DESCRIPTOR_LEN);
+        assert_eq!(&values[..4], &[10.5, 20.5, 2.0, 0.25]);
+    }
+
+    #[test]
+    fn affine_form_is_scaled_rotation() {
+        let kp = Keypoint::new(10.0, 20.0, 2.0, FRAC_PI_2, 0, 1, 0.0);
+        let desc = vec![0.0; COLMAP_DESCRIPTOR_LEN];
+        let values = feature_line(&write_features(&[kp], &desc, true).unwrap());
+        assert_eq!(values.len(), 6 + COLMAP_DESCRIPTOR_LEN);
+        assert_eq!(&values[..2], &[10.5, 20.5]);
+        // 2 * [cos -sin; sin cos](pi/2)
+        for (v, expected) in values[2..6].iter().zip([0.0, -2.0, 2.0, 0.0]) {
+            assert!((v - expected).abs() < 1e-6, "{:?}", &values[2..6]);
+        }
+    }
+
+    #[test]
+    fn descriptors_use_lowe_bin_order() {
+        let kp = Keypoint::new(1.0, 1.0, 2.0, 0.0, 0, 1, 0.0);
+        let mut desc = vec![0.0; COLMAP_DESCRIPTOR_LEN];
+        desc[8 * 5] = 0.25; // cell 5, bin 0 stays
+        desc[8 * 5 + 1] = 0.125; // cell 5, bin 1 becomes bin 7
+        let values = feature_line(&write_features(&[kp], &desc, false).unwrap());
+        let d = &values[4..];
+        assert_eq!((d[8 * 5], d[8 * 5 + 7]), (128.0, 64.0));
+        assert_eq!(d.iter().filter(|&&v| v != 0.0).count(), 2);
+    }
+
+    #[test]
+    fn outlier_pairs_are_dropped() {
+        let matches = [0, 3, 1, 4, 2, 5];
+        let all = write_match_block("a.jpg", "b.jpg", &matches, &[]).unwrap();
+        assert_eq!(all, "a.jpg b.jpg\n0 3\n1 4\n2 5\n\n");
+        let inliers = write_match_block("a.jpg", "b.jpg", &matches, &[1, 0, 1]).unwrap();
+        assert_eq!(inliers, "a.jpg b.jpg\n0 3\n2 5\n\n");
+
+        assert!(write_match_block("a.jpg", "b.jpg", &matches, &[1, 0]).is_err());
+        assert!(write_match_block("my image.jpg", "b.jpg", &matches, &[]).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+use crate::error::SiftError;
+use crate::octaves::first_octave;
+use wasm_bindgen::prelude::*;
+
+/// Tunable detection parameters for `sift_with_config`.
+///
+/// Defaults follow Lowe's paper: 3 scales per octave, sigma0 = 1.6,
+/// an assumed camera blur of 0.5, a DoG contrast threshold of 0.03 and
+/// an edge ratio of 10. `max_octaves` is unbounded by default, i.e. octaves
+/// are built until the image becomes smaller than 16 px. `upsample` adds a
+/// doubled-resolution first octave (octave -1) as in Lowe's paper; it is off
+/// by default because it roughly quadruples the pyramid cost. `max_features`
+/// (unbounded by default) keeps only the N keypoints with the strongest DoG
+/// response before descriptors are computed, like OpenCV's `nfeatures`.
+///
+/// `distribution` optionally spreads the keypoints over the image before that
+/// cap is applied: `Anms` keeps about `anms_target` keypoints with suppression
+/// via square covering (SSC), `Grid` keeps at most `grid_cell_cap` of the
+/// strongest keypoints in each cell of a `grid_cols` x `grid_rows` grid, which
+/// may not have more columns or rows than the image has pixels.
+///
+/// `descriptor_norm` selects how descriptors are normalised; the default is
+/// Lowe's L2 / clip at `clip_threshold` (0.2) / L2 scheme.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct SiftConfig {
+    scales: usize,
+    sigma0: f32,
+    sigma_n: f32,
+    contrast_thresh: f32,
+    edge_r: f32,
+    max_octaves: Option<usize>,
+    upsample: bool,
+    max_features: Option<usize>,
+    distribution: KeypointDistribution,
+    anms_target: usize,
+    grid_cols: usize,
+    grid_rows: usize,
+    grid_cell_cap: usize,
+    descriptor_norm: DescriptorNorm,
+    clip_threshold: f32,
+}
+
+/// Descriptor normalisation, see `SiftConfig`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorNorm {
+    /// L2-normalise, clip at `clip_threshold`, L2-normalise again (Lowe).
+    ClippedL2 = 0,
+    /// L2-normalise without clipping.
+    L2 = 1,
+    /// L1-normalise, then element-wise square root (RootSIFT).
+    RootSift = 2,
+}
+
+/// Spatial filtering applied to the detected keypoints, see `SiftConfig`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeypointDistribution {
+    /// Keep every detected keypoint.
+    All = 0,
+    /// Adaptive non-maximal suppression (SSC) down to `anms_target` keypoints.
+    Anms = 1,
+    /// At most `grid_cell_cap` keypoints per grid cell.
+    Grid = 2,
+}
+
+impl Default for SiftConfig {
+    fn default() -> Self {
+        SiftConfig {
+            scales: 3,
+            sigma0: 1.6,
+            sigma_n: 0.5,
+            contrast_thresh: 0.03,
+            edge_r: 10.0,
+            max_octaves: None,
+            upsample: false,
+            max_features: None,
+            distribution: KeypointDistribution::All,
+            anms_target: 1000,
+            grid_cols: 8,
+            grid_rows: 8,
+            grid_cell_cap: 16,
+            descriptor_norm: DescriptorNorm::ClippedL2,
+            clip_threshold: 0.2,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SiftConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SiftConfig {
+        SiftConfig::default()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn scales(&self) -> usize {
+        self.scales
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_scales(&mut self, value: usize) {
+        self.scales = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sigma0(&self) -> f32 {
+        self.sigma0
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_sigma0(&mut self, value: f32) {
+        self.sigma0 = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn sigma_n(&self) -> f32 {
+        self.sigma_n
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_sigma_n(&mut self, value: f32) {
+        self.sigma_n = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn contrast_thresh(&self) -> f32 {
+        self.contrast_thresh
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_contrast_thresh(&mut self, value: f32) {
+        self.contrast_thresh = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn edge_r(&self) -> f32 {
+        self.edge_r
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_edge_r(&mut self, value: f32) {
+        self.edge_r = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_octaves(&self) -> Option<usize> {
+        self.max_octaves
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_max_octaves(&mut self, value: Option<usize>) {
+        self.max_octaves = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn upsample(&self) -> bool {
+        self.upsample
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_upsample(&mut self, value: bool) {
+        self.upsample = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_features(&self) -> Option<usize> {
+        self.max_features
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_max_features(&mut self, value: Option<usize>) {
+        self.max_features = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn distribution(&self) -> KeypointDistribution {
+        self.distribution
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_distribution(&mut self, value: KeypointDistribution) {
+        self.distribution = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn anms_target(&self) -> usize {
+        self.anms_target
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_anms_target(&mut self, value: usize) {
+        self.anms_target = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn grid_cols(&self) -> usize {
+        self.grid_cols
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_grid_cols(&mut self, value: usize) {
+        self.grid_cols = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn grid_rows(&self) -> usize {
+        self.grid_rows
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_grid_rows(&mut self, value: usize) {
+        self.grid_rows = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn grid_cell_cap(&self) -> usize {
+        self.grid_cell_cap
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_grid_cell_cap(&mut self, value: usize) {
+        self.grid_cell_cap = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn descriptor_norm(&self) -> DescriptorNorm {
+        self.descriptor_norm
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_descriptor_norm(&mut self, value: DescriptorNorm) {
+        self.descriptor_norm = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn clip_threshold(&self) -> f32 {
+        self.clip_threshold
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_clip_threshold(&mut self, value: f32) {
+        self.clip_threshold = value;
+    }
+}
+
+impl SiftConfig {
+    // checks every parameter and reports the first invalid one
+    pub fn validate(&self) -> Result<(), SiftError> {
+        if self.scales < 1 {
+            return Err(SiftError::invalid_parameter("scales", format!("must be >= 1, got {}", self.scales)));
+        }
+        if !(self.sigma0.is_finite() && self.sigma0 > 0.0) {
+            return Err(SiftError::invalid_parameter("sigma0", format!("must be > 0, got {}", self.sigma0)));
+        }
+        if !(self.sigma_n.is_finite() && self.sigma_n >= 0.0) {
+            return Err(SiftError::invalid_parameter("sigma_n", format!("must be >= 0, got {}", self.sigma_n)));
+        }
+        if !(self.contrast_thresh.is_finite() && self.contrast_thresh >= 0.0) {
+            return Err(SiftError::invalid_parameter(
+                "contrast_thresh",
+                format!("must be >= 0, got {}", self.contrast_thresh),
+            ));
+        }
+        if !(self.edge_r.is_finite() && self.edge_r > 0.0) {
+            return Err(SiftError::invalid_parameter("edge_r", format!("must be > 0, got {}", self.edge_r)));
+        }
+        if self.max_octaves == Some(0) {
+            return Err(SiftError::invalid_parameter("max_octaves", "must be >= 1 when set"));
+        }
+        if self.max_features == Some(0) {
+            return Err(SiftError::invalid_parameter("max_features", "must be >= 1 when set"));
+        }
+        if !(self.clip_threshold.is_finite() && self.clip_threshold > 0.0) {
+            return Err(SiftError::invalid_parameter(
+                "clip_threshold",
+                format!("must be > 0, got {}", self.clip_threshold),
+            ));
+        }
+        match self.distribution {
+            KeypointDistribution::All => {}
+            KeypointDistribution::Anms => {
+                if self.anms_target < 1 {
+                    return Err(SiftError::invalid_parameter("anms_target", "must be >= 1"));
+                }
+            }
+            KeypointDistribution::Grid => {
+                if self.grid_cols < 1 || self.grid_rows < 1 {
+                    return Err(SiftError::invalid_parameter(
+                        "grid_cols",
+                        format!("grid must be at least 1x1, got {}x{}", self.grid_cols, self.grid_rows),
+                    ));
+                }
+                if self.grid_cols.checked_mul(self.grid_rows).is_none() {
+                    return Err(SiftError::invalid_parameter(
+                        "grid_cols",
+                        format!("grid of {}x{} cells is too large", self.grid_cols, self.grid_rows),
+                    ));
+                }
+                if self.grid_cell_cap < 1 {
+                    return Err(SiftError::invalid_parameter("grid_cell_cap", "must be >= 1"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // checks the parameters that depend on the image size: a bucketing grid
+    // may not have more columns or rows than the image has pixels
+    pub fn validate_for_image(&self, width: u32, height: u32) -> Result<(), SiftError> {
+        self.validate()?;
+        if self.distribution == KeypointDistribution::Grid
+            && (self.grid_cols > width as usize || self.grid_rows > height as usize)
+        {
+            return Err(SiftError::invalid_parameter(
+                "grid_cols",
+                format!(
+                    "grid of {}x{} cells is larger than the {}x{} image",
+                    self.grid_cols, self.grid_rows, width, height
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn with_scales(mut self, scales: usize) -> Self {
+        self.scales = scales;
+        self
+    }
+
+    // scale multiplier between two adjacent levels of an octave
+    pub fn k(&self) -> f32 {
+        2.0_f32.powf(1.0 / self.scales as f32)
+    }
+
+    // octave index of the first pyramid level, -1 when upsampling
+    pub fn first_octave(&self) -> i32 {
+        first_octave(self.upsample)
+    }
+}
+
+/// Parameters shared by the RANSAC model estimators.
+///
+/// `reproj_threshold` is the inlier distance in pixels, `confidence` the
+/// probability of having drawn at least one outlier-free sample before the
+/// adaptive iteration count stops the search, and `max_iterations` a hard cap.
+/// `seed` makes the sampling reproducible.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct RansacConfig {
+    reproj_threshold: f32,
+    confidence: f32,
+    max_iterations: usize,
+    seed: u32,
+}
+
+impl Default for RansacConfig {
+    fn default() -> Self {
+        RansacConfig {
+            reproj_threshold: 3.0,
+            confidence: 0.995,
+            max_iterations: 2000,
+            seed: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl RansacConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RansacConfig {
+        RansacConfig::default()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn reproj_threshold(&self) -> f32 {
+        self.reproj_threshold
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_reproj_threshold(&mut self, value: f32) {
+        self.reproj_threshold = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_confidence(&mut self, value: f32) {
+        self.confidence = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_max_iterations(&mut self, value: usize) {
+        self.max_iterations = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_seed(&mut self, value: u32) {
+        self.seed = value;
+    }
+}
+
+impl RansacConfig {
+    pub fn validate(&self) -> Result<(), SiftError> {
+        if !(self.reproj_threshold.is_finite() && self.reproj_threshold > 0.0) {
+            return Err(SiftError::invalid_parameter(
+                "reproj_threshold",
+                format!("must be > 0, got {}", self.reproj_threshold),
+            ));
+        }
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(SiftError::invalid_parameter(
+                "confidence",
+                format!("must be in (0, 1), got {}", self.confidence),
+            ));
+        }
+        if self.max_iterations < 1 {
+            return Err(SiftError::invalid_parameter("max_iterations", "must be >= 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Nearest-neighbour search used by the descriptor matcher.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchAlgorithm {
+    /// Exhaustive scan of every descriptor; exact, O(n1 * n2).
+    BruteForce = 0,
+    /// Randomised kd-forest; approximate, much faster on large sets.
+    KdForest = 1,
+}
+
+/// Matcher settings for `match_descriptors_topk_with_config`.
+///
+/// With `KdForest`, `trees` randomised kd-trees are built once over each
+/// descriptor set and every query compares at most `checks` descriptors, so
+/// `checks` trades precision for speed; a budget of at least the set size
+/// searches exhaustively and gives the brute-force result. `seed` makes the
+/// tree construction reproducible.
+/// The ratio test and cross check behave as in the brute-force matcher.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct MatchConfig {
+    algorithm: MatchAlgorithm,
+    trees: usize,
+    checks: usize,
+    seed: u32,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            algorithm: MatchAlgorithm::BruteForce,
+            trees: 4,
+            checks: 128,
+            seed: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl MatchConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> MatchConfig {
+        MatchConfig::default()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn algorithm(&self) -> MatchAlgorithm {
+        self.algorithm
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_algorithm(&mut self, value: MatchAlgorithm) {
+        self.algorithm = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn trees(&self) -> usize {
+        self.trees
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_trees(&mut self, value: usize) {
+        self.trees = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn checks(&self) -> usize {
+        self.checks
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_checks(&mut self, value: usize) {
+        self.checks = value;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_seed(&mut self, value: u32) {
+        self.seed = value;
+    }
+}
+
+impl MatchConfig {
+    pub fn validate(&self) -> Result<(), SiftError> {
+        if self.trees < 1 {
+            return Err(SiftError::invalid_parameter("trees", "must be >= 1"));
+        }
+        if self.checks < 1 {
+            return Err(SiftError::invalid_parameter("checks", "must be >= 1"));
+        }
+        Ok(())
+    }
+}
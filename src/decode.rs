@@ -0,0 +1,346 @@
+// Decoding of PNG, JPEG and PGM files to the 8-bit grayscale buffers the
+// pipeline works on. Colour images are reduced with the same Rec. 601 weights
+// as `rgba_to_gray`, alpha is ignored, and an EXIF orientation tag is applied
+// so that keypoints refer to the image as it is meant to be displayed.
+
+use crate::error::{pixel_count, SiftError};
+use crate::rgb_to_gray::luma;
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+/// Decoded, upright 8-bit grayscale image, see `decode_gray`.
+#[wasm_bindgen]
+pub struct GrayImage {
+    pub(crate) data: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+#[wasm_bindgen]
+impl GrayImage {
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+    /// Moves `data` out without cloning it; afterwards `data` is empty.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Pgm,
+}
+
+// Identifies the format from the leading magic bytes
+pub fn detect_format(bytes: &[u8]) -> Option<ImageFormat> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some(ImageFormat::Png),
+        [0xFF, 0xD8, ..] => Some(ImageFormat::Jpeg),
+        [b'P', b'2' | b'5', ..] => Some(ImageFormat::Pgm),
+        _ => None,
+    }
+}
+
+pub fn decode_gray(bytes: &[u8]) -> Result<GrayImage, SiftError> {
+    match detect_format(bytes) {
+        Some(ImageFormat::Png) => decode_png(bytes),
+        Some(ImageFormat::Jpeg) => decode_jpeg(bytes),
+        Some(ImageFormat::Pgm) => decode_pgm(bytes),
+        None => Err(SiftError::decode("unknown format, expected PNG, JPEG or PGM")),
+    }
+}
+
+// Reduces interleaved 8-bit samples with `channels` per pixel to gray
+fn to_gray(samples: &[u8], channels: usize) -> Vec<u8> {
+    match channels {
+        1 => samples.to_vec(),
+        // gray + alpha
+        2 => samples.chunks_exact(2).map(|p| p[0]).collect(),
+        _ => samples.chunks_exact(channels).map(|p| luma(p[0], p[1], p[2])).collect(),
+    }
+}
+
+// 16-bit counterpart of `to_gray`: the luma is computed at full precision and
+// rounded to 8 bits once, rather than truncating every sample to its high byte
+fn to_gray16(samples: &[u16], channels: usize) -> Vec<u8> {
+    samples
+        .chunks_exact(channels)
+        .map(|p| {
+            let l = if channels < 3 {
+                p[0] as f32
+            } else {
+                0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32
+            };
+            (l / 257.0).round() as u8
+        })
+        .collect()
+}
+
+fn decode_png(bytes: &[u8]) -> Result<GrayImage, SiftError> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    // palette and sub-byte depths to 8-bit samples; 16-bit samples are kept
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| SiftError::decode(format!("PNG: {}", e)))?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| SiftError::decode("PNG image too large"))?;
+    let mut buf = vec![0; size];
+    let frame = reader.next_frame(&mut buf).map_err(|e| SiftError::decode(format!("PNG: {}", e)))?;
+    buf.truncate(frame.buffer_size());
+
+    let channels = frame.color_type.samples();
+    let data = match frame.bit_depth {
+        png::BitDepth::Sixteen => {
+            // PNG stores 16-bit samples most significant byte first
+            let samples: Vec<u16> = buf.chunks_exact(2).map(|p| u16::from_be_bytes([p[0], p[1]])).collect();
+            to_gray16(&samples, channels)
+        }
+        _ => to_gray(&buf, channels),
+    };
+    let image = GrayImage {
+        data,
+        width: frame.width,
+        height: frame.height,
+    };
+    let orientation = reader.info().exif_metadata.as_deref().map_or(1, exif_orientation);
+    Ok(apply_orientation(image, orientation))
+}
+
+fn decode_jpeg(bytes: &[u8]) -> Result<GrayImage, SiftError> {
+    let mut decoder = jpeg_decoder::Decoder::new(bytes);
+    let pixels = decoder.decode().map_err(|e| SiftError::decode(format!("JPEG: {}", e)))?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| SiftError::decode("missing JPEG frame header"))?;
+
+    let data = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 | jpeg_decoder::PixelFormat::L16 => {
+            let precision = jpeg_precision(bytes).unwrap_or(8);
+            if precision == 8 {
+                pixels
+            } else {
+                // lossless frames of any other precision come as native-endian
+                // 16-bit samples, also the 2- to 7-bit ones reported as L8
+                let max = (1u32 << precision) - 1;
+                pixels
+                    .chunks_exact(2)
+                    .map(|p| scale_sample(u16::from_ne_bytes([p[0], p[1]]) as u32, max))
+                    .collect()
+            }
+        }
+        jpeg_decoder::PixelFormat::RGB24 => to_gray(&pixels, 3),
+        // the decoder delivers inverted CMYK, i.e. (255 - c, 255 - m, 255 - y, 255 - k)
+        jpeg_decoder::PixelFormat::CMYK32 => pixels
+            .chunks_exact(4)
+            .map(|p| {
+                let k = p[3] as u32;
+                let ch = |v: u8| ((v as u32 * k + 127) / 255) as u8;
+                luma(ch(p[0]), ch(p[1]), ch(p[2]))
+            })
+            .collect(),
+    };
+
+    let image = GrayImage {
+        data,
+        width: info.width as u32,
+        height: info.height as u32,
+    };
+    let orientation = decoder.exif_data().map_or(1, exif_orientation);
+    Ok(apply_orientation(image, orientation))
+}
+
+// Sample precision in bits from the frame header (SOFn) of a JPEG stream
+fn jpeg_precision(bytes: &[u8]) -> Option<u32> {
+    let mut pos = 2; // past SOI
+    loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // fill byte before a marker
+            0xFF => pos += 1,
+            // markers without a length
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            // SOF0 to SOF15, except DHT, JPG and DAC
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return bytes.get(pos + 4).map(|&p| p as u32);
+            }
+            _ => {
+                let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]);
+                pos += 2 + len as usize;
+            }
+        }
+    }
+}
+
+// Rescales a sample in 0..=max to 0..=255 with rounding
+fn scale_sample(v: u32, max: u32) -> u8 {
+    ((v.min(max) * 255 + max / 2) / max) as u8
+}
+
+// Value of the EXIF orientation tag (1 to 8) in a TIFF-structured EXIF block,
+// 1 (upright) if it is missing or unreadable
+fn exif_orientation(exif: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_raw(exif.to_vec())
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .filter(|o| (1..=8).contains(o))
+        .unwrap_or(1)
+}
+
+// Turns the stored raster upright according to an EXIF orientation: 2 to 4
+// mirror and/or rotate by 180 degrees, 5 to 8 also swap width and height
+fn apply_orientation(image: GrayImage, orientation: u32) -> GrayImage {
+    if orientation == 1 {
+        return image;
+    }
+    let (w, h) = (image.width as usize, image.height as usize);
+    let (out_w, out_h) = if orientation >= 5 { (h, w) } else { (w, h) };
+    let mut data = Vec::with_capacity(w * h);
+    for y in 0..out_h {
+        for x in 0..out_w {
+            // source pixel shown at (x, y)
+            let (sx, sy) = match orientation {
+                2 => (w - 1 - x, y),
+                3 => (w - 1 - x, h - 1 - y),
+                4 => (x, h - 1 - y),
+                5 => (y, x),
+                6 => (y, h - 1 - x),
+                7 => (w - 1 - y, h - 1 - x),
+                _ => (w - 1 - y, x),
+            };
+            data.push(image.data[sy * w + sx]);
+        }
+    }
+    GrayImage {
+        data,
+        width: out_w as u32,
+        height: out_h as u32,
+    }
+}
+
+// Parses width, height and maxval from a PGM header, skipping comments, and
+// returns them with the offset of the raster
+fn pgm_header(bytes: &[u8]) -> Result<([u32; 3], usize), SiftError> {
+    let mut fields = [0u32; 3];
+    let mut pos = 2; // past the magic number
+    for field in fields.iter_mut() {
+        // whitespace and comments up to the next token
+        loop {
+            match bytes.get(pos) {
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = pos;
+        while bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        *field = std::str::from_utf8(&bytes[start..pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| SiftError::decode("malformed PGM header"))?;
+    }
+    // a single whitespace byte separates the header from a binary raster
+    if !bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        return Err(SiftError::decode("malformed PGM header"));
+    }
+    Ok((fields, pos + 1))
+}
+
+fn decode_pgm(bytes: &[u8]) -> Result<GrayImage, SiftError> {
+    let ([width, height, maxval], offset) = pgm_header(bytes)?;
+    if maxval == 0 || maxval > u16::MAX as u32 {
+        return Err(SiftError::decode(format!("PGM maxval {} out of range", maxval)));
+    }
+    let n = pixel_count(width, height)?;
+    let raster = &bytes[offset..];
+
+    let samples: Vec<u32> = if bytes[1] == b'2' {
+        std::str::from_utf8(raster)
+            .map_err(|_| SiftError::decode("non-ASCII data in plain PGM"))?
+            .split_ascii_whitespace()
+            .take(n)
+            .map(|t| t.parse().map_err(|_| SiftError::decode(format!("bad PGM sample {:?}", t))))
+            .collect::<Result<_, _>>()?
+    } else if maxval < 256 {
+        raster.iter().take(n).map(|&v| v as u32).collect()
+    } else {
+        // two bytes per sample, most significant first
+        raster
+            .chunks_exact(2)
+            .take(n)
+            .map(|p| u16::from_be_bytes([p[0], p[1]]) as u32)
+            .collect()
+    };
+    if samples.len() != n {
+        return Err(SiftError::decode(format!(
+            "PGM raster has {} samples, expected {}",
+            samples.len(),
+            n
+        )));
+    }
+
+    Ok(GrayImage {
+        data: samples
+            .into_iter()
+            .map(|v| scale_sample(v, maxval))
+            .collect(),
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x4 lossless (SOF3) gray JPEG of the given precision in which every
+    // sample equals the initial predictor 2^(precision - 1): all differences
+    // are 0, coded with a single 1-bit Huffman code
+    fn flat_lossless_jpeg(precision: u8) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xC3, 0, 11, precision, 0, 4, 0, 4, 1, 1, 0x11, 0]);
+        jpeg.extend_from_slice(&[0xFF, 0xC4, 0, 20, 0x00, 1]);
+        jpeg.extend_from_slice(&[0; 15]);
+        jpeg.push(0);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 8, 1, 1, 0x00, 1, 0, 0]);
+        jpeg.extend_from_slice(&[0x00, 0x00, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn lossless_jpeg_is_scaled_by_its_precision() {
+        for precision in [4u8, 12, 16] {
+            let max = (1u32 << precision) - 1;
+            let expected = scale_sample(1 << (precision - 1), max);
+            let image = decode_gray(&flat_lossless_jpeg(precision)).unwrap();
+            assert_eq!((image.width, image.height), (4, 4));
+            assert_eq!(image.data, vec![expected; 16], "precision {}", precision);
+            assert!((127..=136).contains(&expected));
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Element types descriptors can be stored in, and the blocked brute-force
+// nearest-neighbour search built on the simd distance kernels. Matching and
+// the kd-forest are generic over these, so f32 and quantised u8 descriptors
+// share one code path. Squared distances are returned as f32: for u8
+// descriptors they are integers up to d * 255^2, exact in f32 while that
+// stays below 2^24, i.e. for d <= 258 (`MAX_DIM`).
+
+use crate::par;
+use crate::simd::{l2_sq_f32, l2_sq_f32_x4, l2_sq_u8, l2_sq_u8_x4};
+
+// queries per block and candidates per tile of the batch search: a tile of
+// 64 f32 descriptors is 32 KB and stays cached while the block reuses it
+const QUERY_BLOCK: usize = 8;
+const SET_TILE: usize = 64;
+
+pub trait DescriptorValue: Copy + Send + Sync {
+    // largest descriptor length whose squared distances are exact
+    const MAX_DIM: usize;
+    fn to_f32(self) -> f32;
+    // squared Euclidean distance between two descriptors of equal length
+    fn l2_sq(a: &[Self], b: &[Self]) -> f32;
+    // squared distances from `q` to four candidates at once
+    fn l2_sq_x4(q: &[Self], c: [&[Self]; 4]) -> [f32; 4];
+}
+
+impl DescriptorValue for f32 {
+    const MAX_DIM: usize = usize::MAX;
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn l2_sq(a: &[f32], b: &[f32]) -> f32 {
+        l2_sq_f32(a, b)
+    }
+    fn l2_sq_x4(q: &[f32], c: [&[f32]; 4]) -> [f32; 4] {
+        l2_sq_f32_x4(q, c)
+    }
+}
+
+impl DescriptorValue for u8 {
+    // 258 * 255^2 < 2^24 <= 259 * 255^2
+    const MAX_DIM: usize = (1 << 24) / (255 * 255);
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn l2_sq(a: &[u8], b: &[u8]) -> f32 {
+        l2_sq_u8(a, b) as f32
+    }
+    fn l2_sq_x4(q: &[u8], c: [&[u8]; 4]) -> [f32; 4] {
+        l2_sq_u8_x4(q, c).map(|v| v as f32)
+    }
+}
+
+// Exhaustive two-nearest-neighbour search for every row of `queries` in
+// `set`: returns (index of best, best squared distance, second squared
+// distance) per query, or None when `set` is empty. The distance matrix is
+// walked in QUERY_BLOCK x SET_TILE blocks, four candidates per kernel call.
+// Candidates are visited in index order, so ties resolve to the lower index.
+pub fn best_two_batch<T: DescriptorValue>(
+    queries: &[T],
+    set: &[T],
+    d: usize,
+) -> Vec<Option<(usize, f32, f32)>> {
+    let nq = queries.len() / d;
+    let ns = set.len() / d;
+    if ns == 0 {
+        return vec![None; nq];
+    }
+
+    let mut best = vec![(usize::MAX, f32::INFINITY, f32::INFINITY); nq];
+    par::for_each_chunk_mut(&mut best, QUERY_BLOCK, |block, block_best| {
+        let q0 = block * QUERY_BLOCK;
+        for s0 in (0..ns).step_by(SET_TILE) {
+            let s1 = (s0 + SET_TILE).min(ns);
+            for (qi, b) in block_best.iter_mut().enumerate() {
+                let q = row(queries, q0 + qi, d);
+                let mut j = s0;
+                while j + 4 <= s1 {
+                    let cands = [row(set, j, d), row(set, j + 1, d), row(set, j + 2, d), row(set, j + 3, d)];
+                    let dists = T::l2_sq_x4(q, cands);
+                    for (k, &dist) in dists.iter().enumerate() {
+                        push_candidate(b, j + k, dist);
+                    }
+                    j += 4;
+                }
+                for jj in j..s1 {
+                    push_candidate(b, jj, T::l2_sq(q, row(set, jj, d)));
+                }
+            }
+        }
+    });
+    best.into_iter().map(Some).collect()
+}
+
+#[inline]
+fn row<T>(data: &[T], i: usize, d: usize) -> &[T] {
+    &data[i * d..(i + 1) * d]
+}
+
+#[inline]
+fn push_candidate(best: &mut (usize, f32, f32), j: usize, dist: f32) {
+    if dist < best.1 {
+        *best = (j, dist, best.1);
+    } else if dist < best.2 {
+        best.2 = dist;
+    }
+}
@@ -0,0 +1,211 @@
+// Post-detection filters that spread keypoints over the image instead of
+// letting them pile up in textured regions. Both keep the survivors in
+// detection order and run before descriptor extraction.
+
+use crate::error::SiftError;
+use crate::keypoints::Keypoint;
+
+// SSC stops once the selected count is within this fraction of the target
+const SSC_TOLERANCE: f64 = 0.1;
+
+// Drops every keypoint whose flag is false
+fn retain_flags(kps: &mut Vec<Keypoint>, keep: &[bool]) {
+    let mut flags = keep.iter();
+    kps.retain(|_| *flags.next().unwrap_or(&false));
+}
+
+// Indices of `kps` sorted by descending response (stable, so ties keep
+// detection order and orientation twins stay adjacent)
+fn by_response(kps: &[Keypoint]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..kps.len()).collect();
+    order.sort_by(|&a, &b| kps[b].response().total_cmp(&kps[a].response()));
+    order
+}
+
+// Suppression via Square Covering (Bailo et al. 2018): binary search for the
+// suppression width that leaves about `target` keypoints, where each selected
+// keypoint, strongest first, covers a square of that width around itself.
+// Keypoints sharing a position with a selected one (extra orientations of the
+// same extremum) are kept along with it.
+pub fn ssc(kps: &mut Vec<Keypoint>, target: usize, width: u32, height: u32) {
+    if kps.len() <= target {
+        return;
+    }
+    let order = by_response(kps);
+    let (cols, rows, k) = (width as f64, height as f64, target as f64);
+    let n = kps.len() as f64;
+
+    // bounds of the search from the paper's closed form
+    let exp1 = rows + cols + 2.0 * k;
+    let exp2 = 4.0 * cols + 4.0 * k + 4.0 * rows * k + rows * rows + cols * cols
+        - 2.0 * rows * cols
+        + 4.0 * rows * cols * k;
+    let exp3 = exp2.sqrt();
+    let exp4 = k - 1.0;
+    let (mut low, mut high) = if exp4 > 0.0 {
+        let sol1 = -((exp1 + exp3) / exp4).round();
+        let sol2 = -((exp1 - exp3) / exp4).round();
+        ((n / k).sqrt().floor(), sol1.max(sol2))
+    } else {
+        // a single keypoint: any width spanning the image will do
+        (1.0, cols.max(rows))
+    };
+    low = low.max(1.0);
+    high = high.max(low);
+
+    let k_min = (k - k * SSC_TOLERANCE).round() as usize;
+    let k_max = (k + k * SSC_TOLERANCE).round() as usize;
+
+    let mut result = Vec::new();
+    let mut prev_width = -1.0;
+    while low <= high {
+        let w = (low + (high - low) / 2.0).floor();
+        if w == prev_width {
+            break;
+        }
+        prev_width = w;
+
+        let selected = ssc_select(kps, &order, w, cols, rows);
+        let count = selected.len();
+        result = selected;
+        if (k_min..=k_max).contains(&count) {
+            break;
+        } else if count < k_min {
+            high = w - 1.0;
+        } else {
+            low = w + 1.0;
+        }
+    }
+
+    let mut keep = vec![false; kps.len()];
+    for i in result {
+        keep[i] = true;
+    }
+    retain_flags(kps, &keep);
+}
+
+// One covering pass of SSC at suppression width `w`
+fn ssc_select(kps: &[Keypoint], order: &[usize], w: f64, cols: f64, rows: f64) -> Vec<usize> {
+    let c = (w / 2.0).max(1.0);
+    let grid_cols = (cols / c) as usize + 1;
+    let grid_rows = (rows / c) as usize + 1;
+    let reach = (w / c).floor() as isize;
+    let mut covered = vec![false; grid_cols * grid_rows];
+
+    let mut selected = Vec::new();
+    let mut last: Option<(f32, f32)> = None;
+    for &i in order {
+        let kp = &kps[i];
+        if last == Some((kp.x(), kp.y())) {
+            selected.push(i);
+            continue;
+        }
+        let row = ((kp.y().max(0.0) as f64 / c) as usize).min(grid_rows - 1);
+        let col = ((kp.x().max(0.0) as f64 / c) as usize).min(grid_cols - 1);
+        if covered[row * grid_cols + col] {
+            continue;
+        }
+        selected.push(i);
+        last = Some((kp.x(), kp.y()));
+
+        let r0 = (row as isize - reach).max(0) as usize;
+        let r1 = ((row as isize + reach) as usize).min(grid_rows - 1);
+        let c0 = (col as isize - reach).max(0) as usize;
+        let c1 = ((col as isize + reach) as usize).min(grid_cols - 1);
+        for r in r0..=r1 {
+            covered[r * grid_cols + c0..=r * grid_cols + c1].fill(true);
+        }
+    }
+    selected
+}
+
+// Splits the image into `grid_cols` x `grid_rows` cells and keeps at most
+// `cell_cap` keypoints per cell, strongest first
+pub fn grid_bucket(
+    kps: &mut Vec<Keypoint>,
+    grid_cols: usize,
+    grid_rows: usize,
+    cell_cap: usize,
+    width: u32,
+    height: u32,
+) -> Result<(), SiftError> {
+    let cells = grid_cols
+        .checked_mul(grid_rows)
+        .ok_or_else(|| SiftError::invalid_parameter("grid_cols", "grid has too many cells"))?;
+    let cell_w = width as f32 / grid_cols as f32;
+    let cell_h = height as f32 / grid_rows as f32;
+    let mut counts = vec![0usize; cells];
+    let mut keep = vec![false; kps.len()];
+    for i in by_response(kps) {
+        let kp = &kps[i];
+        let col = ((kp.x().max(0.0) / cell_w) as usize).min(grid_cols - 1);
+        let row = ((kp.y().max(0.0) / cell_h) as usize).min(grid_rows - 1);
+        let count = &mut counts[row * grid_cols + col];
+        if *count < cell_cap {
+            *count += 1;
+            keep[i] = true;
+        }
+    }
+    retain_flags(kps, &keep);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeypointDistribution, SiftConfig};
+    use crate::rng::Rng;
+
+    // n keypoints spread uniformly over a width x height image
+    fn scattered(n: usize, width: u32, height: u32) -> Vec<Keypoint> {
+        let mut rng = Rng::new(3);
+        (0..n)
+            .map(|_| {
+                let x = rng.below(width as usize) as f32;
+                let y = rng.below(height as usize) as f32;
+                let response = rng.below(1000) as f32 / 1000.0;
+                Keypoint::new(x, y, 1.6, 0.0, 0, 1, response)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ssc_keeps_about_the_target() {
+        let mut kps = scattered(2000, 640, 480);
+        ssc(&mut kps, 200, 640, 480);
+        assert!((180..=220).contains(&kps.len()), "kept {}", kps.len());
+    }
+
+    #[test]
+    fn grid_bucket_caps_every_cell_and_keeps_the_strongest() {
+        let mut kps = scattered(2000, 640, 480);
+        let all = kps.clone();
+        grid_bucket(&mut kps, 4, 3, 5, 640, 480).unwrap();
+        assert_eq!(kps.len(), 4 * 3 * 5);
+
+        // every kept keypoint is among the 5 strongest of its 160 x 160 cell
+        let cell = |kp: &Keypoint| ((kp.x() / 160.0) as usize, (kp.y() / 160.0) as usize);
+        for kp in &kps {
+            let stronger = all
+                .iter()
+                .filter(|o| cell(o) == cell(kp) && o.response() > kp.response())
+                .count();
+            assert!(stronger < 5);
+        }
+    }
+
+    #[test]
+    fn oversized_grids_are_rejected() {
+        let mut config = SiftConfig::default();
+        config.set_distribution(KeypointDistribution::Grid);
+        config.set_grid_cols(usize::MAX);
+        config.set_grid_rows(2);
+        assert!(matches!(config.validate(), Err(SiftError::InvalidParameter { .. })));
+
+        config.set_grid_cols(65);
+        config.set_grid_rows(8);
+        assert!(config.validate().is_ok());
+        assert!(config.validate_for_image(64, 64).is_err());
+        assert!(config.validate_for_image(65, 8).is_ok());
+    }
+}
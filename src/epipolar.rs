@@ -0,0 +1,497 @@
+use crate::config::RansacConfig;
+use crate::error::SiftError;
+use crate::linalg::{
+    characteristic_polynomial, eigenvector, mat3_from_slice, mat3_inverse, mat3_mul,
+    mat3_transpose, null_space, null_vector, real_roots, solve_linear, svd3, Mat3,
+};
+use crate::ransac::{self, check_correspondences, normalize_points};
+
+// Minimal sample sizes of the two solvers
+const FUNDAMENTAL_SAMPLE: usize = 8;
+const ESSENTIAL_SAMPLE: usize = 5;
+
+pub struct EpipolarModel {
+    pub matrix: Mat3,
+    pub inliers: Vec<bool>,
+    pub sampson_errors: Vec<f32>, // Sampson distance in pixels, per match
+}
+
+fn sampson_sq(f: &Mat3, p: [f64; 2], q: [f64; 2]) -> f64 {
+    // first-order approximation of the squared geometric distance of the
+    // correspondence p <-> q to the epipolar constraint q^T F p = 0
+    let fp = [
+        f[0][0] * p[0] + f[0][1] * p[1] + f[0][2],
+        f[1][0] * p[0] + f[1][1] * p[1] + f[1][2],
+        f[2][0] * p[0] + f[2][1] * p[1] + f[2][2],
+    ];
+    let ftq = [
+        f[0][0] * q[0] + f[1][0] * q[1] + f[2][0],
+        f[0][1] * q[0] + f[1][1] * q[1] + f[2][1],
+    ];
+    let qfp = q[0] * fp[0] + q[1] * fp[1] + fp[2];
+    let denom = fp[0] * fp[0] + fp[1] * fp[1] + ftq[0] * ftq[0] + ftq[1] * ftq[1];
+    if denom < 1e-24 {
+        return f64::INFINITY;
+    }
+    qfp * qfp / denom
+}
+
+fn epipolar_rows(src: &[[f64; 2]], dst: &[[f64; 2]], indices: &[usize]) -> Vec<f64> {
+    // one row of the linear system q^T F p = 0 per correspondence
+    let mut a = Vec::with_capacity(indices.len() * 9);
+    for &i in indices {
+        let [x, y] = src[i];
+        let [u, v] = dst[i];
+        a.extend_from_slice(&[u * x, u * y, u, v * x, v * y, v, x, y, 1.0]);
+    }
+    a
+}
+
+fn with_singular_values(m: &Mat3, s: [f64; 3]) -> Mat3 {
+    // U diag(s) V^T, with U and V taken from the SVD of m
+    let (u, _, v) = svd3(m);
+    let mut us = u;
+    for row in us.iter_mut() {
+        for (col, val) in row.iter_mut().enumerate() {
+            *val *= s[col];
+        }
+    }
+    mat3_mul(&us, &mat3_transpose(&v))
+}
+
+fn eight_point(src: &[[f64; 2]], dst: &[[f64; 2]], indices: &[usize]) -> Option<Mat3> {
+    // linear solution in normalised coordinates, then rank-2 projection
+    let a = epipolar_rows(src, dst, indices);
+    let f = null_vector(&a, indices.len(), 9);
+    if f.iter().any(|v| !v.is_finite()) {
+        return None;
+    }
+    let f = mat3_from_slice(&f);
+    let (_, s, _) = svd3(&f);
+    Some(with_singular_values(&f, [s[0], s[1], 0.0]))
+}
+
+fn normalize_scale(m: &Mat3) -> Mat3 {
+    // unit Frobenius norm
+    let norm = m.iter().flatten().map(|v| v * v).sum::<f64>().sqrt();
+    let mut out = *m;
+    if norm > 0.0 {
+        for v in out.iter_mut().flatten() {
+            *v /= norm;
+        }
+    }
+    out
+}
+
+// Monomials in (x, y, z) up to degree 3. The ten cubics come first: they are
+// eliminated, and the remaining ten form the basis of the quotient ring used by
+// the action matrix: x^2, xy, xz, y^2, yz, z^2, x, y, z, 1.
+const MONOMIALS: [[u8; 3]; 20] = [
+    [3, 0, 0],
+    [2, 1, 0],
+    [2, 0, 1],
+    [1, 2, 0],
+    [1, 1, 1],
+    [1, 0, 2],
+    [0, 3, 0],
+    [0, 2, 1],
+    [0, 1, 2],
+    [0, 0, 3],
+    [2, 0, 0],
+    [1, 1, 0],
+    [1, 0, 1],
+    [0, 2, 0],
+    [0, 1, 1],
+    [0, 0, 2],
+    [1, 0, 0],
+    [0, 1, 0],
+    [0, 0, 1],
+    [0, 0, 0],
+];
+
+type Poly = [f64; 20];
+
+fn monomial_index(e: [u8; 3]) -> usize {
+    MONOMIALS.iter().position(|&m| m == e).unwrap_or(19)
+}
+
+fn poly_mul(a: &Poly, b: &Poly) -> Poly {
+    // product of two polynomials whose degrees add up to at most 3
+    let mut out = [0.0f64; 20];
+    for (i, &ca) in a.iter().enumerate() {
+        if ca == 0.0 {
+            continue;
+        }
+        for (j, &cb) in b.iter().enumerate() {
+            if cb == 0.0 {
+                continue;
+            }
+            let e = [
+                MONOMIALS[i][0] + MONOMIALS[j][0],
+                MONOMIALS[i][1] + MONOMIALS[j][1],
+                MONOMIALS[i][2] + MONOMIALS[j][2],
+            ];
+            out[monomial_index(e)] += ca * cb;
+        }
+    }
+    out
+}
+
+fn poly_add(a: &Poly, b: &Poly, scale: f64) -> Poly {
+    let mut out = *a;
+    for (o, v) in out.iter_mut().zip(b.iter()) {
+        *o += scale * v;
+    }
+    out
+}
+
+fn five_point(src: &[[f64; 2]], dst: &[[f64; 2]], indices: &[usize]) -> Vec<Mat3> {
+    // Stewenius' Groebner-basis 5-point solver on calibrated coordinates. The
+    // essential matrix lies in the 4-D null space of the epipolar rows,
+    // E = x X + y Y + z Z + W, and must satisfy det(E) = 0 and
+    // 2 E E^T E - tr(E E^T) E = 0: ten cubics in (x, y, z) with up to ten
+    // solutions. x runs over the real roots of the characteristic polynomial
+    // of the action matrix of x, and y, z are read off its eigenvectors.
+    let a = epipolar_rows(src, dst, indices);
+    let basis = null_space(&a, indices.len(), 9, 4);
+    if basis.len() < 4 {
+        return Vec::new();
+    }
+
+    // E as a 3x3 matrix of degree-1 polynomials
+    let mut e = [[[0.0f64; 20]; 3]; 3];
+    for (k, entry) in e.iter_mut().flatten().enumerate() {
+        entry[16..].copy_from_slice(&[basis[0][k], basis[1][k], basis[2][k], basis[3][k]]);
+    }
+
+    // ten constraint rows: det(E) and the nine trace constraints
+    let mut constraints: Vec<Poly> = Vec::with_capacity(10);
+    let det = poly_add(
+        &poly_add(
+            &poly_mul(&e[0][0], &poly_add(&poly_mul(&e[1][1], &e[2][2]), &poly_mul(&e[1][2], &e[2][1]), -1.0)),
+            &poly_mul(&e[0][1], &poly_add(&poly_mul(&e[1][0], &e[2][2]), &poly_mul(&e[1][2], &e[2][0]), -1.0)),
+            -1.0,
+        ),
+        &poly_mul(&e[0][2], &poly_add(&poly_mul(&e[1][0], &e[2][1]), &poly_mul(&e[1][1], &e[2][0]), -1.0)),
+        1.0,
+    );
+    constraints.push(det);
+
+    let mut eet = [[[0.0f64; 20]; 3]; 3];
+    for (r, eet_row) in eet.iter_mut().enumerate() {
+        for (c, entry) in eet_row.iter_mut().enumerate() {
+            for (a, b) in e[r].iter().zip(&e[c]) {
+                *entry = poly_add(entry, &poly_mul(a, b), 1.0);
+            }
+        }
+    }
+    let trace = poly_add(&poly_add(&eet[0][0], &eet[1][1], 1.0), &eet[2][2], 1.0);
+    for (e_row, eet_row) in e.iter().zip(&eet) {
+        for (c, e_rc) in e_row.iter().enumerate() {
+            let mut row = poly_mul(&trace, e_rc);
+            for v in row.iter_mut() {
+                *v = -*v;
+            }
+            for (eet_rk, e_k) in eet_row.iter().zip(&e) {
+                row = poly_add(&row, &poly_mul(eet_rk, &e_k[c]), 2.0);
+            }
+            constraints.push(row);
+        }
+    }
+
+    // Eliminate the cubic monomials: solve C_cubic * G = C_basis column by column
+    let mut reduced = [[0.0f64; 10]; 10];
+    for col in 0..10 {
+        let mut lhs = vec![0.0f64; 100];
+        let mut rhs = vec![0.0f64; 10];
+        for (row, poly) in constraints.iter().enumerate() {
+            lhs[row * 10..(row + 1) * 10].copy_from_slice(&poly[..10]);
+            rhs[row] = poly[10 + col];
+        }
+        solve_linear(&mut lhs, &mut rhs, 10);
+        for row in 0..10 {
+            reduced[row][col] = rhs[row];
+        }
+    }
+    if reduced.iter().flatten().any(|v| !v.is_finite()) {
+        return Vec::new();
+    }
+
+    // Action matrix of multiplication by x on the basis
+    // [x^2, xy, xz, y^2, yz, z^2, x, y, z, 1]: the first six products are
+    // cubics (x^3, x^2y, x^2z, xy^2, xyz, xz^2 = minus their reduced rows),
+    // the last four land back in the basis (x^2, xy, xz, x).
+    let mut action = vec![0.0f64; 100];
+    for row in 0..6 {
+        for col in 0..10 {
+            action[row * 10 + col] = -reduced[row][col];
+        }
+    }
+    action[6 * 10] = 1.0;
+    action[7 * 10 + 1] = 1.0;
+    action[8 * 10 + 2] = 1.0;
+    action[9 * 10 + 6] = 1.0;
+
+    let mut solutions = Vec::new();
+    for x in real_roots(&characteristic_polynomial(&action, 10)) {
+        let v = eigenvector(&action, 10, x);
+        if v[9].abs() < 1e-12 {
+            continue;
+        }
+        let (x, y, z) = (v[6] / v[9], v[7] / v[9], v[8] / v[9]);
+        let mut m = [[0.0f64; 3]; 3];
+        for (k, val) in m.iter_mut().flatten().enumerate() {
+            *val = x * basis[0][k] + y * basis[1][k] + z * basis[2][k] + basis[3][k];
+        }
+        if m.iter().flatten().all(|v| v.is_finite()) {
+            solutions.push(normalize_scale(&m));
+        }
+    }
+    solutions
+}
+
+fn essential_refit(src: &[[f64; 2]], dst: &[[f64; 2]], indices: &[usize]) -> Option<Mat3> {
+    // linear least squares on all inliers, projected onto the essential
+    // manifold (two equal singular values, third zero)
+    if indices.len() < FUNDAMENTAL_SAMPLE {
+        return None;
+    }
+    let a = epipolar_rows(src, dst, indices);
+    let e = null_vector(&a, indices.len(), 9);
+    if e.iter().any(|v| !v.is_finite()) {
+        return None;
+    }
+    Some(with_singular_values(&mat3_from_slice(&e), [1.0, 1.0, 0.0]))
+}
+
+// Runs RANSAC with a solver whose models live in its own coordinates;
+// `to_pixels` turns a model into the pixel-space fundamental matrix on which
+// inliers and Sampson errors are measured
+fn run_epipolar(
+    src: &[[f64; 2]],
+    dst: &[[f64; 2]],
+    sample_size: usize,
+    config: &RansacConfig,
+    solve: impl Fn(&[usize], bool) -> Vec<Mat3>,
+    to_pixels: impl Fn(&Mat3) -> Mat3,
+) -> Result<EpipolarModel, SiftError> {
+    let consensus = ransac::run(
+        src.len(),
+        sample_size,
+        config,
+        |indices, refit| {
+            solve(indices, refit)
+                .into_iter()
+                .map(|model| {
+                    let f = to_pixels(&model);
+                    (model, f)
+                })
+                .collect()
+        },
+        |(_, f), i| sampson_sq(f, src[i], dst[i]),
+    )?;
+    let (matrix, f) = consensus.model;
+    let sampson_errors = src
+        .iter()
+        .zip(dst)
+        .map(|(&p, &q)| sampson_sq(&f, p, q).sqrt() as f32)
+        .collect();
+    Ok(EpipolarModel {
+        matrix,
+        inliers: consensus.inliers,
+        sampson_errors,
+    })
+}
+
+pub fn estimate_fundamental(
+    src: &[[f64; 2]],
+    dst: &[[f64; 2]],
+    config: &RansacConfig,
+) -> Result<EpipolarModel, SiftError> {
+    // Robustly estimates F with q^T F p = 0 for p in image 1 and q in image 2,
+    // using the normalised 8-point algorithm inside RANSAC
+    config.validate()?;
+    check_correspondences(src, dst, FUNDAMENTAL_SAMPLE)?;
+
+    let (src_norm, t_src) = normalize_points(src);
+    let (dst_norm, t_dst) = normalize_points(dst);
+    let t_dst_t = mat3_transpose(&t_dst);
+
+    let model = run_epipolar(
+        src,
+        dst,
+        FUNDAMENTAL_SAMPLE,
+        config,
+        |indices, _refit| eight_point(&src_norm, &dst_norm, indices).into_iter().collect(),
+        // F = T_dst^T F_norm T_src
+        |f_norm| normalize_scale(&mat3_mul(&mat3_mul(&t_dst_t, f_norm), &t_src)),
+    )?;
+    // report F in pixel coordinates
+    let matrix = normalize_scale(&mat3_mul(&mat3_mul(&t_dst_t, &model.matrix), &t_src));
+    Ok(EpipolarModel { matrix, ..model })
+}
+
+fn camera_matrix(intrinsics: [f64; 4]) -> Mat3 {
+    let [fx, fy, cx, cy] = intrinsics;
+    [[fx, 0.0, cx], [0.0, fy, cy], [0.0, 0.0, 1.0]]
+}
+
+pub fn estimate_essential(
+    src: &[[f64; 2]],
+    dst: &[[f64; 2]],
+    intrinsics1: [f64; 4],
+    intrinsics2: [f64; 4],
+    config: &RansacConfig,
+) -> Result<EpipolarModel, SiftError> {
+    // Robustly estimates E with q^T E p = 0 in calibrated coordinates, using the
+    // 5-point solver inside RANSAC. Intrinsics are [fx, fy, cx, cy] per camera;
+    // inliers and Sampson errors are measured in pixels via F = K2^-T E K1^-1.
+    config.validate()?;
+    check_correspondences(src, dst, ESSENTIAL_SAMPLE)?;
+    for (name, k) in [("intrinsics1", intrinsics1), ("intrinsics2", intrinsics2)] {
+        if !(k.iter().all(|v| v.is_finite()) && k[0] > 0.0 && k[1] > 0.0) {
+            return Err(SiftError::invalid_parameter(
+                name,
+                "expected [fx, fy, cx, cy] with positive focal lengths",
+            ));
+        }
+    }
+
+    let k1_inv = mat3_inverse(&camera_matrix(intrinsics1)).ok_or_else(|| {
+        SiftError::invalid_parameter("intrinsics1", "camera matrix is singular")
+    })?;
+    let k2_inv = mat3_inverse(&camera_matrix(intrinsics2)).ok_or_else(|| {
+        SiftError::invalid_parameter("intrinsics2", "camera matrix is singular")
+    })?;
+    let calibrate = |points: &[[f64; 2]], k_inv: &Mat3| -> Vec<[f64; 2]> {
+        points
+            .iter()
+            .map(|p| {
+                [
+                    k_inv[0][0] * p[0] + k_inv[0][1] * p[1] + k_inv[0][2],
+                    k_inv[1][0] * p[0] + k_inv[1][1] * p[1] + k_inv[1][2],
+                ]
+            })
+            .collect()
+    };
+    let src_cal = calibrate(src, &k1_inv);
+    let dst_cal = calibrate(dst, &k2_inv);
+    let k2_inv_t = mat3_transpose(&k2_inv);
+
+    run_epipolar(
+        src,
+        dst,
+        ESSENTIAL_SAMPLE,
+        config,
+        |indices, refit| {
+            if refit {
+                essential_refit(&src_cal, &dst_cal, indices).into_iter().collect()
+            } else {
+                five_point(&src_cal, &dst_cal, indices)
+            }
+        },
+        |e| mat3_mul(&mat3_mul(&k2_inv_t, e), &k1_inv),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const INTRINSICS: [f64; 4] = [500.0, 500.0, 320.0, 240.0];
+
+    // pixel correspondences, outlier flags and the true essential matrix
+    type TwoViews = (Vec<[f64; 2]>, Vec<[f64; 2]>, Vec<bool>, Mat3);
+
+    // Two calibrated views of a random point cloud: camera 2 is rotated about
+    // y and x and translated by t. Every fourth correspondence is replaced by
+    // an unrelated pair.
+    fn two_views() -> TwoViews {
+        let (a, b) = (0.1f64, 0.05f64);
+        let ry = [[a.cos(), 0.0, a.sin()], [0.0, 1.0, 0.0], [-a.sin(), 0.0, a.cos()]];
+        let rx = [[1.0, 0.0, 0.0], [0.0, b.cos(), -b.sin()], [0.0, b.sin(), b.cos()]];
+        let r = mat3_mul(&rx, &ry);
+        let t = [1.0, 0.1, 0.05];
+        let tx = [[0.0, -t[2], t[1]], [t[2], 0.0, -t[0]], [-t[1], t[0], 0.0]];
+        let e = mat3_mul(&tx, &r);
+
+        let [fx, fy, cx, cy] = INTRINSICS;
+        let project = |p: [f64; 3]| [fx * p[0] / p[2] + cx, fy * p[1] / p[2] + cy];
+        let mut rng = Rng::new(5);
+        let mut uniform = |lo: f64, hi: f64| rng.uniform(lo, hi);
+
+        let (mut src, mut dst, mut is_outlier) = (Vec::new(), Vec::new(), Vec::new());
+        for i in 0..120 {
+            let p = [uniform(-3.0, 3.0), uniform(-2.0, 2.0), uniform(4.0, 10.0)];
+            let q = [
+                r[0][0] * p[0] + r[0][1] * p[1] + r[0][2] * p[2] + t[0],
+                r[1][0] * p[0] + r[1][1] * p[1] + r[1][2] * p[2] + t[1],
+                r[2][0] * p[0] + r[2][1] * p[1] + r[2][2] * p[2] + t[2],
+            ];
+            let outlier = i % 4 == 0;
+            src.push(project(p));
+            dst.push(if outlier {
+                [uniform(0.0, 640.0), uniform(0.0, 480.0)]
+            } else {
+                project(q)
+            });
+            is_outlier.push(outlier);
+        }
+        (src, dst, is_outlier, e)
+    }
+
+    // Largest entry difference of two matrices compared up to scale and sign
+    fn distance_up_to_scale(a: &Mat3, b: &Mat3) -> f64 {
+        let (a, b) = (normalize_scale(a), normalize_scale(b));
+        let diff = |sign: f64| {
+            a.iter()
+                .flatten()
+                .zip(b.iter().flatten())
+                .map(|(x, y)| (x - sign * y).abs())
+                .fold(0.0, f64::max)
+        };
+        diff(1.0).min(diff(-1.0))
+    }
+
+    fn check_inliers(model: &EpipolarModel, is_outlier: &[bool]) {
+        for (i, &outlier) in is_outlier.iter().enumerate() {
+            if !outlier {
+                assert!(model.inliers[i], "inlier {} rejected", i);
+                assert!(model.sampson_errors[i] < 1e-3);
+            }
+        }
+        // unrelated pairs only pass when they happen to lie near their epipolar line
+        let accepted = is_outlier.iter().zip(&model.inliers).filter(|(&o, &m)| o && m).count();
+        assert!(accepted <= 3, "{} outliers accepted", accepted);
+    }
+
+    #[test]
+    fn recovers_fundamental_with_outliers() {
+        let (src, dst, is_outlier, e) = two_views();
+        let k_inv = mat3_inverse(&camera_matrix(INTRINSICS)).unwrap();
+        let truth = mat3_mul(&mat3_mul(&mat3_transpose(&k_inv), &e), &k_inv);
+
+        let model = estimate_fundamental(&src, &dst, &RansacConfig::default()).unwrap();
+        check_inliers(&model, &is_outlier);
+        assert!(distance_up_to_scale(&model.matrix, &truth) < 1e-6);
+    }
+
+    #[test]
+    fn recovers_essential_with_outliers() {
+        let (src, dst, is_outlier, truth) = two_views();
+        let model = estimate_essential(&src, &dst, INTRINSICS, INTRINSICS, &RansacConfig::default()).unwrap();
+        check_inliers(&model, &is_outlier);
+        assert!(distance_up_to_scale(&model.matrix, &truth) < 1e-6);
+    }
+
+    #[test]
+    fn bad_intrinsics_are_rejected() {
+        let (src, dst, _, _) = two_views();
+        let bad = [0.0, 500.0, 320.0, 240.0];
+        assert!(matches!(
+            estimate_essential(&src, &dst, bad, INTRINSICS, &RansacConfig::default()),
+            Err(SiftError::InvalidParameter { .. })
+        ));
+    }
+}
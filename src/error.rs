@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// Errors reported by the public entry points instead of panicking.
+///
+/// `wasm_bindgen::JsError` implements `From` for any `std::error::Error`,
+/// so exports can simply use `?` and the message ends up in the thrown JS error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SiftError {
+    /// Width or height is zero (or the pixel count overflows).
+    InvalidDimensions { width: u32, height: u32 },
+    /// A buffer does not have the length implied by its dimensions.
+    BufferLength {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A scalar argument or config field is out of range.
+    InvalidParameter { name: &'static str, reason: String },
+    /// A geometric model could not be estimated from the given matches.
+    Estimation { reason: String },
+    /// An encoded image or feature file could not be read.
+    Decode { reason: String },
+}
+
+impl fmt::Display for SiftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiftError::InvalidDimensions { width, height } => {
+                write!(f, "invalid image dimensions {}x{}", width, height)
+            }
+            SiftError::BufferLength {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has length {}, expected {}",
+                name, actual, expected
+            ),
+            SiftError::InvalidParameter { name, reason } => {
+                write!(f, "invalid {}: {}", name, reason)
+            }
+            SiftError::Estimation { reason } => write!(f, "estimation failed: {}", reason),
+            SiftError::Decode { reason } => write!(f, "could not decode: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SiftError {}
+
+impl SiftError {
+    pub fn decode(reason: impl Into<String>) -> Self {
+        SiftError::Decode {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn invalid_parameter(name: &'static str, reason: impl Into<String>) -> Self {
+        SiftError::InvalidParameter {
+            name,
+            reason: reason.into(),
+        }
+    }
+}
+
+// Number of pixels of a width x height image, rejecting empty or overflowing sizes
+pub fn pixel_count(width: u32, height: u32) -> Result<usize, SiftError> {
+    if width == 0 || height == 0 {
+        return Err(SiftError::InvalidDimensions { width, height });
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .ok_or(SiftError::InvalidDimensions { width, height })
+}
+
+// Checks that `buffer` holds exactly `channels` values per pixel
+pub fn check_buffer_len(
+    name: &'static str,
+    len: usize,
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<(), SiftError> {
+    let expected = pixel_count(width, height)?
+        .checked_mul(channels)
+        .ok_or(SiftError::InvalidDimensions { width, height })?;
+    if len != expected {
+        return Err(SiftError::BufferLength {
+            name,
+            expected,
+            actual: len,
+        });
+    }
+    Ok(())
+}
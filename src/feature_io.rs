@@ -0,0 +1,503 @@
+// Reading and writing keypoints with their descriptors in the formats of
+// other SIFT implementations, and in a binary format of our own:
+//
+// - Lowe's `.key` text format: a `<n> <d>` header, then per keypoint a
+//   `row col scale orientation` line followed by the `d` descriptor values as
+//   integers, 20 per line.
+// - VLFeat's `.frame` / `.descr` pair as written by its `sift` tool: one
+//   `x y scale orientation` line per keypoint, and one line of `d` integers
+//   per descriptor.
+// - A little-endian binary format with a versioned header, which keeps every
+//   keypoint field and the f32 descriptors exactly.
+//
+// Both text formats store descriptors as integers, i.e. quantised like
+// `quantize_descriptors`, and read them back divided by 512. Note that
+// VLFeat's `sift` tool truncates (`(u8)(512 * v)`) where we round, so its
+// files can hold values one lower than ours for the same descriptor. Positions and
+// scales are in input-image pixels. They carry neither octave, level nor
+// response: on reading, octave and level are derived from the scale as
+// `describe` does and the response is 0.
+//
+// Conventions. The detector measures angles with the y axis pointing down,
+// i.e. from +x towards +y, in [0, 2pi), and lays descriptors out as 4 x 4
+// cells (rows of the rotated patch first, then columns) of 8 orientation bins,
+// bin k covering relative gradient angles around k * 2pi / 8 measured the same
+// way. VLFeat uses exactly this frame and layout, so its files are written as
+// is. Lowe's binary measures angles with the y axis pointing up: orientations
+// are negated and stored in (-pi, pi], and within every cell orientation bin k
+// becomes bin (8 - k) % 8. The cells keep their order: flipping y reverses
+// the sense of rotation but not which cell lies above which, which is also
+// how COLMAP converts VLFeat descriptors to Lowe's. Both are undone on
+// reading.
+
+use crate::config::SiftConfig;
+use crate::error::SiftError;
+use crate::keypoints::{octave_level_for_sigma, quantize_descriptors, Keypoint};
+use std::f32::consts::{PI, TAU};
+use std::fmt::Write;
+
+// Descriptor values per line in Lowe's files
+const LOWE_VALUES_PER_LINE: usize = 20;
+// Lowe's files always hold 128-D descriptors
+const LOWE_DESCRIPTOR_LEN: usize = 128;
+// Orientation bins per descriptor cell
+const ORIENTATION_BINS: usize = 8;
+// Scale of the integer descriptors of the text formats
+const DESCRIPTOR_SCALE: f32 = 512.0;
+
+const BINARY_MAGIC: &[u8; 8] = b"SIFTFEAT";
+const BINARY_VERSION: u32 = 1;
+// magic, version, keypoint count, descriptor length
+const BINARY_HEADER_LEN: usize = 8 + 3 * 4;
+// x, y, sigma, angle, response, octave, level
+const BINARY_RECORD_LEN: usize = 7 * 4;
+
+// Descriptor length of `n` keypoints with the flat descriptors `desc`; 0 for
+// results without descriptors
+fn descriptor_len(n: usize, desc: &[f32]) -> Result<usize, SiftError> {
+    if desc.is_empty() {
+        return Ok(0);
+    }
+    if n == 0 || !desc.len().is_multiple_of(n) {
+        return Err(SiftError::BufferLength {
+            name: "descriptors",
+            expected: n * 128,
+            actual: desc.len(),
+        });
+    }
+    Ok(desc.len() / n)
+}
+
+// Whitespace-separated tokens of a text file, parsed one at a time
+struct Tokens<'a> {
+    name: &'static str,
+    iter: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(name: &'static str, text: &'a str) -> Self {
+        Tokens {
+            name,
+            iter: text.split_ascii_whitespace(),
+        }
+    }
+
+    fn next<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, SiftError> {
+        let token = self
+            .iter
+            .next()
+            .ok_or_else(|| SiftError::invalid_parameter(self.name, format!("unexpected end of file, expected {}", what)))?;
+        token
+            .parse()
+            .map_err(|_| SiftError::invalid_parameter(self.name, format!("bad {} {:?}", what, token)))
+    }
+
+    fn finish(mut self) -> Result<(), SiftError> {
+        match self.iter.next() {
+            Some(token) => Err(SiftError::invalid_parameter(
+                self.name,
+                format!("unexpected trailing data {:?}", token),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// Orientation in Lowe's convention, see the module comment
+fn to_lowe_angle(angle: f32) -> f32 {
+    // `0.0 - angle` rather than `-angle` keeps 0 from being written as -0
+    let flipped = 0.0 - angle;
+    if flipped <= -PI {
+        flipped + TAU
+    } else {
+        flipped
+    }
+}
+
+// Inverse of `to_lowe_angle`, back in [0, 2pi)
+fn from_lowe_angle(angle: f32) -> f32 {
+    let a = (0.0 - angle).rem_euclid(TAU);
+    // rem_euclid rounds tiny negative angles up to 2pi
+    if a >= TAU {
+        0.0
+    } else {
+        a
+    }
+}
+
+// Swaps between our orientation bin order and Lowe's by reversing the bins of
+// every 8-bin cell histogram; the permutation is its own inverse
+pub fn lowe_descriptor_order<T: Copy>(desc: &[T]) -> Vec<T> {
+    let mut out = Vec::with_capacity(desc.len());
+    for cell in desc.chunks_exact(ORIENTATION_BINS) {
+        out.push(cell[0]);
+        out.extend(cell[1..].iter().rev());
+    }
+    out
+}
+
+// Reads one integer descriptor of length `d`, appending it rescaled to `out`
+fn read_descriptor(tokens: &mut Tokens, d: usize, out: &mut Vec<f32>) -> Result<(), SiftError> {
+    for _ in 0..d {
+        let v: u8 = tokens.next("descriptor value")?;
+        out.push(v as f32 / DESCRIPTOR_SCALE);
+    }
+    Ok(())
+}
+
+// Keypoint read from a text format, see the module comment
+fn keypoint_from_frame(
+    name: &'static str,
+    x: f32,
+    y: f32,
+    sigma: f32,
+    angle: f32,
+    config: &SiftConfig,
+) -> Result<Keypoint, SiftError> {
+    if !(x.is_finite() && y.is_finite() && angle.is_finite() && sigma.is_finite() && sigma > 0.0) {
+        return Err(SiftError::invalid_parameter(
+            name,
+            format!("keypoint ({}, {}) has a non-finite value or scale <= 0", x, y),
+        ));
+    }
+    let (octave, level) = octave_level_for_sigma(sigma, config, i32::MAX);
+    Ok(Keypoint::new(x, y, sigma, angle, octave, level, 0.0))
+}
+
+pub fn write_lowe_key(kps: &[Keypoint], desc: &[f32]) -> Result<String, SiftError> {
+    let d = descriptor_len(kps.len(), desc)?;
+    if d != 0 && d != LOWE_DESCRIPTOR_LEN {
+        return Err(SiftError::BufferLength {
+            name: "descriptors",
+            expected: kps.len() * LOWE_DESCRIPTOR_LEN,
+            actual: desc.len(),
+        });
+    }
+    let quantized = quantize_descriptors(&lowe_descriptor_order(desc));
+    let mut out = String::new();
+    // writing to a String cannot fail
+    let _ = writeln!(out, "{} {}", kps.len(), d);
+    for (i, kp) in kps.iter().enumerate() {
+        let _ = writeln!(out, "{} {} {} {}", kp.y(), kp.x(), kp.sigma(), to_lowe_angle(kp.angle()));
+        for line in quantized[i * d..(i + 1) * d].chunks(LOWE_VALUES_PER_LINE) {
+            for v in line {
+                let _ = write!(out, " {}", v);
+            }
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+pub fn read_lowe_key(text: &str, config: &SiftConfig) -> Result<(Vec<Keypoint>, Vec<f32>), SiftError> {
+    config.validate()?;
+    let mut tokens = Tokens::new("key file", text);
+    let n: usize = tokens.next("keypoint count")?;
+    let d: usize = tokens.next("descriptor length")?;
+    n.checked_mul(d)
+        .ok_or_else(|| SiftError::decode(format!("key file header {} x {} overflows", n, d)))?;
+    if d != 0 && d != LOWE_DESCRIPTOR_LEN {
+        return Err(SiftError::decode(format!(
+            "key file descriptor length {}, expected {}",
+            d, LOWE_DESCRIPTOR_LEN
+        )));
+    }
+
+    // the header is untrusted, so nothing is preallocated from it: a short
+    // file runs out of tokens long before the vectors grow large
+    let mut kps = Vec::new();
+    let mut desc = Vec::new();
+    for _ in 0..n {
+        let y = tokens.next("row")?;
+        let x = tokens.next("column")?;
+        let sigma = tokens.next("scale")?;
+        let angle: f32 = tokens.next("orientation")?;
+        kps.push(keypoint_from_frame("key file", x, y, sigma, from_lowe_angle(angle), config)?);
+        read_descriptor(&mut tokens, d, &mut desc)?;
+    }
+    tokens.finish()?;
+    Ok((kps, lowe_descriptor_order(&desc)))
+}
+
+// Returns the `.frame` and `.descr` file contents
+pub fn write_vlfeat(kps: &[Keypoint], desc: &[f32]) -> Result<(String, String), SiftError> {
+    let d = descriptor_len(kps.len(), desc)?;
+    let mut frames = String::new();
+    for kp in kps {
+        let _ = writeln!(frames, "{} {} {} {}", kp.x(), kp.y(), kp.sigma(), kp.angle());
+    }
+    let mut descriptors = String::new();
+    if d > 0 {
+        for row in quantize_descriptors(desc).chunks_exact(d) {
+            let line: Vec<String> = row.iter().map(u8::to_string).collect();
+            descriptors.push_str(&line.join(" "));
+            descriptors.push('\n');
+        }
+    }
+    Ok((frames, descriptors))
+}
+
+// Reads a `.frame` file and its `.descr` file, which may be empty for
+// keypoints without descriptors
+pub fn read_vlfeat(
+    frames: &str,
+    descriptors: &str,
+    config: &SiftConfig,
+) -> Result<(Vec<Keypoint>, Vec<f32>), SiftError> {
+    config.validate()?;
+    let mut kps = Vec::new();
+    for line in frames.lines().filter(|l| !l.trim().is_empty()) {
+        let mut tokens = Tokens::new("frame file", line);
+        let x = tokens.next("x")?;
+        let y = tokens.next("y")?;
+        let sigma = tokens.next("scale")?;
+        let angle = tokens.next("orientation")?;
+        tokens.finish()?;
+        kps.push(keypoint_from_frame("frame file", x, y, sigma, angle, config)?);
+    }
+
+    let rows: Vec<&str> = descriptors.lines().filter(|l| !l.trim().is_empty()).collect();
+    if rows.is_empty() {
+        return Ok((kps, Vec::new()));
+    }
+    if rows.len() != kps.len() {
+        return Err(SiftError::invalid_parameter(
+            "descr file",
+            format!("has {} descriptors for {} frames", rows.len(), kps.len()),
+        ));
+    }
+    let d = rows[0].split_ascii_whitespace().count();
+    let mut desc = Vec::new();
+    for row in rows {
+        let mut tokens = Tokens::new("descr file", row);
+        read_descriptor(&mut tokens, d, &mut desc)?;
+        tokens.finish()?;
+    }
+    Ok((kps, desc))
+}
+
+pub fn write_binary(kps: &[Keypoint], desc: &[f32]) -> Result<Vec<u8>, SiftError> {
+    let d = descriptor_len(kps.len(), desc)?;
+    let mut out = Vec::with_capacity(BINARY_HEADER_LEN + kps.len() * BINARY_RECORD_LEN + desc.len() * 4);
+    out.extend_from_slice(BINARY_MAGIC);
+    for v in [BINARY_VERSION, kps.len() as u32, d as u32] {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    for kp in kps {
+        for v in [kp.x(), kp.y(), kp.sigma(), kp.angle(), kp.response()] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&kp.octave().to_le_bytes());
+        out.extend_from_slice(&(kp.level() as u32).to_le_bytes());
+    }
+    for v in desc {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    Ok(out)
+}
+
+pub fn read_binary(bytes: &[u8]) -> Result<(Vec<Keypoint>, Vec<f32>), SiftError> {
+    let word = |offset: usize| -> [u8; 4] { bytes[offset..offset + 4].try_into().unwrap() };
+
+    if bytes.len() < BINARY_HEADER_LEN || &bytes[..8] != BINARY_MAGIC {
+        return Err(SiftError::invalid_parameter("features", "not a binary feature file"));
+    }
+    let version = u32::from_le_bytes(word(8));
+    if version != BINARY_VERSION {
+        return Err(SiftError::invalid_parameter(
+            "features",
+            format!("unsupported version {}, expected {}", version, BINARY_VERSION),
+        ));
+    }
+    let n = u32::from_le_bytes(word(12)) as usize;
+    let d = u32::from_le_bytes(word(16)) as usize;
+    // usize is 32 bits on wasm, so every size derived from the header is checked
+    let expected = d
+        .checked_mul(4)
+        .and_then(|descriptor| descriptor.checked_add(BINARY_RECORD_LEN))
+        .and_then(|record| n.checked_mul(record))
+        .and_then(|body| body.checked_add(BINARY_HEADER_LEN))
+        .ok_or_else(|| SiftError::decode(format!("binary feature header {} x {} overflows", n, d)))?;
+    if bytes.len() != expected {
+        return Err(SiftError::BufferLength {
+            name: "features",
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    let mut kps = Vec::with_capacity(n);
+    for i in 0..n {
+        let base = BINARY_HEADER_LEN + i * BINARY_RECORD_LEN;
+        let f = |k: usize| f32::from_le_bytes(word(base + 4 * k));
+        let octave = i32::from_le_bytes(word(base + 20));
+        let level = u32::from_le_bytes(word(base + 24)) as usize;
+        kps.push(Keypoint::new(f(0), f(1), f(2), f(3), octave, level, f(4)));
+    }
+    let desc = bytes[BINARY_HEADER_LEN + n * BINARY_RECORD_LEN..]
+        .chunks_exact(4)
+        .map(|w| f32::from_le_bytes(w.try_into().unwrap()))
+        .collect();
+    Ok((kps, desc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // Keypoints with varied geometry and 128-D descriptors of plausible
+    // magnitudes, including angles right at the ends of [0, 2pi)
+    fn features(n: usize) -> (Vec<Keypoint>, Vec<f32>) {
+        let mut rng = Rng::new(7);
+        let mut unit = || rng.unit_f32();
+        let mut kps = Vec::new();
+        let mut desc = Vec::new();
+        for i in 0..n {
+            let angle = match i {
+                0 => 0.0,
+                1 => PI,
+                2 => TAU - 1e-6,
+                _ => unit() * TAU,
+            };
+            let sigma = 1.6 + 20.0 * unit();
+            kps.push(Keypoint::new(400.0 * unit(), 300.0 * unit(), sigma, angle, 1, 2, unit()));
+            desc.extend((0..LOWE_DESCRIPTOR_LEN).map(|_| 0.4 * unit()));
+        }
+        (kps, desc)
+    }
+
+    fn dequantized(desc: &[f32]) -> Vec<f32> {
+        quantize_descriptors(desc).iter().map(|&v| v as f32 / DESCRIPTOR_SCALE).collect()
+    }
+
+    fn angle_diff(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(TAU);
+        d.min(TAU - d)
+    }
+
+    #[test]
+    fn binary_round_trip_is_exact() {
+        let (kps, desc) = features(20);
+        let (read, read_desc) = read_binary(&write_binary(&kps, &desc).unwrap()).unwrap();
+        assert_eq!(read_desc, desc);
+        assert_eq!(read.len(), kps.len());
+        for (a, b) in kps.iter().zip(&read) {
+            assert_eq!(
+                (a.x(), a.y(), a.sigma(), a.angle(), a.response(), a.octave(), a.level()),
+                (b.x(), b.y(), b.sigma(), b.angle(), b.response(), b.octave(), b.level())
+            );
+        }
+    }
+
+    #[test]
+    fn lowe_round_trip() {
+        let (kps, desc) = features(20);
+        let text = write_lowe_key(&kps, &desc).unwrap();
+        let (read, read_desc) = read_lowe_key(&text, &SiftConfig::default()).unwrap();
+        assert_eq!(read_desc, dequantized(&desc));
+        assert_eq!(read.len(), kps.len());
+        for (a, b) in kps.iter().zip(&read) {
+            assert_eq!((a.x(), a.y(), a.sigma()), (b.x(), b.y(), b.sigma()));
+            assert!(angle_diff(a.angle(), b.angle()) < 1e-6);
+            assert!((0.0..TAU).contains(&b.angle()));
+        }
+    }
+
+    #[test]
+    fn vlfeat_round_trip() {
+        let (kps, desc) = features(20);
+        let (frames, descr) = write_vlfeat(&kps, &desc).unwrap();
+        let (read, read_desc) = read_vlfeat(&frames, &descr, &SiftConfig::default()).unwrap();
+        assert_eq!(read_desc, dequantized(&desc));
+        assert_eq!(read.len(), kps.len());
+        for (a, b) in kps.iter().zip(&read) {
+            assert_eq!((a.x(), a.y(), a.sigma(), a.angle()), (b.x(), b.y(), b.sigma(), b.angle()));
+        }
+    }
+
+    #[test]
+    fn lowe_uses_y_up_angles_and_reversed_orientation_bins() {
+        // pointing down the image is -pi/2 for Lowe
+        let kp = Keypoint::new(10.0, 20.0, 2.0, PI / 2.0, 0, 1, 0.0);
+        let mut desc = vec![0.0; LOWE_DESCRIPTOR_LEN];
+        desc[ORIENTATION_BINS + 1] = 0.25; // cell 1, bin 1
+        let text = write_lowe_key(&[kp], &desc).unwrap();
+        let mut lines = text.lines().skip(1);
+        assert_eq!(lines.next().unwrap(), format!("20 10 2 {}", -PI / 2.0));
+        let values: Vec<u8> = lines.flat_map(|l| l.split_whitespace()).map(|v| v.parse().unwrap()).collect();
+        assert_eq!(values[ORIENTATION_BINS + 7], 128);
+        assert_eq!(values.iter().filter(|&&v| v != 0).count(), 1);
+
+        // the frame and descr files keep our convention
+        let (frames, descr) = write_vlfeat(&[Keypoint::new(10.0, 20.0, 2.0, PI / 2.0, 0, 1, 0.0)], &desc).unwrap();
+        assert_eq!(frames.trim(), format!("10 20 2 {}", PI / 2.0));
+        assert_eq!(descr.split_whitespace().nth(ORIENTATION_BINS + 1), Some("128"));
+    }
+
+    // Lowe-format descriptor of a keypoint at the centre of `image`, by cell
+    fn lowe_cells(image: &[u8], size: u32, angle: f32) -> Vec<Vec<u8>> {
+        let c = size as f32 / 2.0;
+        let flat = [c, c, 0.0, 0.0, 3.0, angle];
+        let desc = crate::run_describe(image, size, size, &flat, &SiftConfig::default(), true).unwrap();
+        let kp = Keypoint::new(c, c, 3.0, angle, 0, 0, 0.0);
+        let text = write_lowe_key(&[kp], &desc).unwrap();
+        let values: Vec<u8> = text
+            .lines()
+            .skip(2)
+            .flat_map(|l| l.split_whitespace())
+            .map(|v| v.parse().unwrap())
+            .collect();
+        values.chunks_exact(ORIENTATION_BINS).map(|cell| cell.to_vec()).collect()
+    }
+
+    fn strongest_bin(cell: &[u8]) -> usize {
+        (0..cell.len()).max_by_key(|&k| cell[k]).unwrap_or(0)
+    }
+
+    // Checks the written files against Lowe's definitions rather than against
+    // our own conversion: with the y axis pointing up, angles and orientation
+    // bins run counterclockwise and cell row 0 is the top of the patch.
+    #[test]
+    fn lowe_descriptors_follow_the_y_up_convention() {
+        let size = 64u32;
+        // brightness grows towards the top of the image: gradients point up,
+        // which is +pi/2 for Lowe
+        let ramp: Vec<u8> = (0..size * size).map(|i| (40 + 2 * (size - i / size)) as u8).collect();
+        for (angle, expected_bin) in [(0.0, 2), (PI / 4.0, 3), (3.0 * PI / 2.0, 0)] {
+            // our angle pi/4 is -pi/4 for Lowe, so the gradient is at 3pi/4
+            // relative to the keypoint; 3pi/2 points up, along the gradient
+            for cell in lowe_cells(&ramp, size, angle) {
+                assert_eq!(strongest_bin(&cell), expected_bin, "angle {}", angle);
+            }
+        }
+
+        // a ramp only above the keypoint fills the top two rows of cells (the
+        // blur of the pyramid spreads some of it into the rows below)
+        let half: Vec<u8> = (0..size * size)
+            .map(|i| (40 + 3 * (size / 2).saturating_sub(i / size)) as u8)
+            .collect();
+        let cells = lowe_cells(&half, size, 0.0);
+        let mass = |rows: std::ops::Range<usize>| -> u32 {
+            rows.flat_map(|r| &cells[r * 4..r * 4 + 4]).flatten().map(|&v| v as u32).sum()
+        };
+        assert!(mass(0..2) > 4 * mass(2..4), "{} vs {}", mass(0..2), mass(2..4));
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let huge = format!("{} {}", usize::MAX, 128);
+        assert!(matches!(
+            read_lowe_key(&huge, &SiftConfig::default()),
+            Err(SiftError::Decode { .. })
+        ));
+        // header claims far more keypoints than the file holds
+        assert!(read_lowe_key("1000000000 128\n1 2 3 0", &SiftConfig::default()).is_err());
+
+        let mut bytes = BINARY_MAGIC.to_vec();
+        for v in [BINARY_VERSION, u32::MAX, u32::MAX] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        assert!(read_binary(&bytes).is_err());
+    }
+}
@@ -1,3 +1,9 @@
+use crate::error::{check_buffer_len, SiftError};
+use crate::par;
+use crate::simd::axpy;
+
+// rows per parallel task in the separable convolution
+const ROW_BLOCK: usize = 16;
 
 pub struct Grid<T> {
     pub width: u32,
@@ -5,17 +11,6 @@ pub struct Grid<T> {
     pub data: Vec<T>,
 }
 
-#[inline]
-fn clamp_coord(coord: i32, min_coord: i32, max_coord: i32) -> i32 {
-    if coord < min_coord {
-        min_coord
-    } else if coord > max_coord {
-        max_coord
-    } else {
-        coord
-    }
-}
-
 impl<T> Grid<T>
 where
     T: Copy ,
@@ -27,6 +22,11 @@ where
             data: buffer.to_vec(),
         }
     }
+    pub fn try_new(buffer: &[T], width: u32, height: u32) -> Result<Self, SiftError> {
+        // like `new`, but checks that the buffer really is width x height
+        check_buffer_len("image buffer", buffer.len(), width, height, 1)?;
+        Ok(Grid::new(buffer, width, height))
+    }
     pub fn get_width(&self) -> u32 {
         self.width
     }
@@ -42,11 +42,6 @@ where
         self.data[idx]
     }
 
-    pub fn set_pixel(&mut self, x: u32, y: u32, value: T) {
-        let idx = (y * self.width + x) as usize;
-        self.data[idx] = value;
-    }
-
     pub fn new_filled(width: u32, height: u32, value: T) -> Self {
         let len = (width as usize) * (height as usize);
         Grid {
@@ -68,42 +63,46 @@ where
 
 
 impl Grid<f32> {
-    pub fn convolve(&self, kernel: &Grid<f32>) -> Grid<f32> {
-        let width = self.get_width();
-        let height = self.get_height();
-
-        let kernel_width = kernel.get_width();
-        let kernel_height = kernel.get_height();
-
-        assert!(kernel_width % 2 == 1 && kernel_height % 2 == 1, "Kernel must be odd");
-
-        let radius_x = (kernel_width / 2) as i32;
-        let radius_y = (kernel_height / 2) as i32;
-
-        let mut out = Grid::new_filled(width, height, 0_f32);
-
-        let max_x = (width as i32) - 1;
-        let max_y = (height as i32) - 1;
-
-        for y in 0..height {
-            for x in 0..width {
-                let mut acc: f32 = 0.0;
-                for ky in 0..kernel_height {
-                    for kx in 0..kernel_width {
-                        let dx = (kx as i32) - radius_x;
-                        let dy = (ky as i32) - radius_y;
-
-                        let sx = clamp_coord(x as i32 + dx, 0, max_x) as u32;
-                        let sy = clamp_coord(y as i32 + dy, 0, max_y) as u32;
-
-                        let img_v = self.get_pixel(sx, sy);
-                        let k_v = kernel.get_pixel(kx, ky);
-                        acc += img_v * k_v;
-                    }
+    pub fn convolve_separable(&self, kernel: &[f32]) -> Grid<f32> {
+        // Convolves with `kernel` along x and then along y, i.e. with the 2-D
+        // kernel kernel^T * kernel. Borders are clamp-extended: rows are padded
+        // once before the horizontal pass and the vertical pass clamps whole
+        // row indices, so the inner loops are plain multiply-adds.
+        assert!(kernel.len() % 2 == 1, "Kernel must be odd");
+
+        let width = self.get_width() as usize;
+        let height = self.get_height() as usize;
+        let radius = kernel.len() / 2;
+
+        // Horizontal pass over a clamp-padded copy of each row
+        let mut horizontal = vec![0.0f32; width * height];
+        par::for_each_chunk_mut(&mut horizontal, ROW_BLOCK * width, |block, out_rows| {
+            let mut padded = vec![0.0f32; width + 2 * radius];
+            for (r, out_row) in out_rows.chunks_exact_mut(width).enumerate() {
+                let y = block * ROW_BLOCK + r;
+                let row = &self.data[y * width..(y + 1) * width];
+                padded[..radius].fill(row[0]);
+                padded[radius..radius + width].copy_from_slice(row);
+                padded[radius + width..].fill(row[width - 1]);
+
+                for (k, &k_v) in kernel.iter().enumerate() {
+                    axpy(out_row, &padded[k..k + width], k_v);
                 }
-                out.set_pixel(x, y, acc);
             }
-        }
+        });
+
+        // Vertical pass, accumulating whole (clamped) source rows
+        let mut out = Grid::new_filled(width as u32, height as u32, 0_f32);
+        let max_y = height as isize - 1;
+        par::for_each_chunk_mut(&mut out.data, ROW_BLOCK * width, |block, out_rows| {
+            for (r, out_row) in out_rows.chunks_exact_mut(width).enumerate() {
+                let y = block * ROW_BLOCK + r;
+                for (k, &k_v) in kernel.iter().enumerate() {
+                    let sy = (y as isize + k as isize - radius as isize).clamp(0, max_y) as usize;
+                    axpy(out_row, &horizontal[sy * width..(sy + 1) * width], k_v);
+                }
+            }
+        });
         out
     }
 }
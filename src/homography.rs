@@ -0,0 +1,165 @@
+use crate::config::RansacConfig;
+use crate::error::SiftError;
+use crate::linalg::{mat3_from_slice, mat3_inverse, mat3_mul, null_vector, Mat3};
+use crate::ransac::{self, check_correspondences, normalize_points};
+
+// Four correspondences determine a homography
+const SAMPLE_SIZE: usize = 4;
+
+pub struct Homography {
+    pub matrix: Mat3,
+    pub inliers: Vec<bool>,
+}
+
+fn dlt(src: &[[f64; 2]], dst: &[[f64; 2]], indices: &[usize]) -> Option<Mat3> {
+    // Direct linear transform: each correspondence (x, y) -> (u, v) adds two rows
+    // to A, and h is the unit vector minimising |A h|
+    let mut a = Vec::with_capacity(indices.len() * 18);
+    for &i in indices {
+        let [x, y] = src[i];
+        let [u, v] = dst[i];
+        a.extend_from_slice(&[-x, -y, -1.0, 0.0, 0.0, 0.0, u * x, u * y, u]);
+        a.extend_from_slice(&[0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v]);
+    }
+    let h = null_vector(&a, indices.len() * 2, 9);
+    if h.iter().any(|v| !v.is_finite()) {
+        return None;
+    }
+    Some(mat3_from_slice(&h))
+}
+
+fn is_degenerate(points: &[[f64; 2]], indices: &[usize]) -> bool {
+    // a minimal sample is useless if any three of its points are (nearly) collinear
+    for a in 0..indices.len() {
+        for b in (a + 1)..indices.len() {
+            for c in (b + 1)..indices.len() {
+                let [x0, y0] = points[indices[a]];
+                let [x1, y1] = points[indices[b]];
+                let [x2, y2] = points[indices[c]];
+                let area = (x1 - x0) * (y2 - y0) - (y1 - y0) * (x2 - x0);
+                if area.abs() < 1e-6 {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn transfer_error_sq(h: &Mat3, p: [f64; 2], q: [f64; 2]) -> f64 {
+    // squared distance between H * p and q in the second image
+    let w = h[2][0] * p[0] + h[2][1] * p[1] + h[2][2];
+    if w.abs() < 1e-12 {
+        return f64::INFINITY;
+    }
+    let x = (h[0][0] * p[0] + h[0][1] * p[1] + h[0][2]) / w;
+    let y = (h[1][0] * p[0] + h[1][1] * p[1] + h[1][2]) / w;
+    (x - q[0]).powi(2) + (y - q[1]).powi(2)
+}
+
+fn denormalize(h_norm: &Mat3, t_src: &Mat3, t_dst_inv: &Mat3) -> Mat3 {
+    // H = T_dst^-1 * H_norm * T_src, scaled so that H[2][2] = 1 when possible
+    let mut h = mat3_mul(&mat3_mul(t_dst_inv, h_norm), t_src);
+    let scale = if h[2][2].abs() > 1e-12 {
+        h[2][2]
+    } else {
+        h.iter().flatten().map(|v| v * v).sum::<f64>().sqrt()
+    };
+    for v in h.iter_mut().flatten() {
+        *v /= scale;
+    }
+    h
+}
+
+pub fn estimate_homography(
+    src: &[[f64; 2]],
+    dst: &[[f64; 2]],
+    config: &RansacConfig,
+) -> Result<Homography, SiftError> {
+    // Robustly estimates H with dst ~ H * src: normalised 4-point DLT inside
+    // RANSAC, followed by least-squares refits on the inlier set
+    config.validate()?;
+    check_correspondences(src, dst, SAMPLE_SIZE)?;
+
+    let (src_norm, t_src) = normalize_points(src);
+    let (dst_norm, t_dst) = normalize_points(dst);
+    let t_dst_inv = mat3_inverse(&t_dst).ok_or_else(|| SiftError::Estimation {
+        reason: "degenerate point distribution".to_string(),
+    })?;
+
+    let consensus = ransac::run(
+        src.len(),
+        SAMPLE_SIZE,
+        config,
+        |indices, refit| {
+            if !refit && (is_degenerate(&src_norm, indices) || is_degenerate(&dst_norm, indices)) {
+                return Vec::new();
+            }
+            dlt(&src_norm, &dst_norm, indices)
+                .map(|h_norm| denormalize(&h_norm, &t_src, &t_dst_inv))
+                .into_iter()
+                .collect()
+        },
+        |h, i| transfer_error_sq(h, src[i], dst[i]),
+    )?;
+
+    Ok(Homography {
+        matrix: consensus.model,
+        inliers: consensus.inliers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn apply(h: &Mat3, p: [f64; 2]) -> [f64; 2] {
+        let w = h[2][0] * p[0] + h[2][1] * p[1] + h[2][2];
+        [
+            (h[0][0] * p[0] + h[0][1] * p[1] + h[0][2]) / w,
+            (h[1][0] * p[0] + h[1][1] * p[1] + h[1][2]) / w,
+        ]
+    }
+
+    #[test]
+    fn recovers_homography_with_outliers() {
+        let truth = [[0.9, -0.2, 30.0], [0.15, 1.1, -12.0], [1e-4, -2e-4, 1.0]];
+        let mut rng = Rng::new(11);
+        let mut coord = |extent: f64| rng.uniform(0.0, extent);
+        let mut src = Vec::new();
+        let mut dst = Vec::new();
+        let mut is_outlier = Vec::new();
+        for i in 0..100 {
+            let p = [coord(640.0), coord(480.0)];
+            // every fourth correspondence is an unrelated point
+            let outlier = i % 4 == 0;
+            let q = if outlier { [coord(640.0), coord(480.0)] } else { apply(&truth, p) };
+            src.push(p);
+            dst.push(q);
+            is_outlier.push(outlier);
+        }
+
+        let result = estimate_homography(&src, &dst, &RansacConfig::default()).unwrap();
+        for i in 0..src.len() {
+            if !is_outlier[i] {
+                assert!(result.inliers[i], "inlier {} rejected", i);
+            }
+            let [x, y] = apply(&result.matrix, src[i]);
+            let [u, v] = apply(&truth, src[i]);
+            assert!((x - u).abs() < 1e-6 && (y - v).abs() < 1e-6);
+        }
+        // an unrelated point lands within the threshold only by chance
+        let accepted = (0..src.len()).filter(|&i| is_outlier[i] && result.inliers[i]).count();
+        assert!(accepted <= 1);
+    }
+
+    #[test]
+    fn too_few_matches_are_rejected() {
+        let pts = vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]];
+        assert!(matches!(
+            estimate_homography(&pts, &pts, &RansacConfig::default()),
+            Err(SiftError::InvalidParameter { .. })
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::{check_buffer_len, pixel_count, SiftError};
 
 pub fn calculate_resize_dimensions(width: u32, height: u32, target_long_edge: u32) -> (u32, u32) {
     let max_dimension = width.max(height);
@@ -7,8 +8,9 @@ pub fn calculate_resize_dimensions(width: u32, height: u32, target_long_edge: u3
     }
 
     let scale = target_long_edge as f32 / max_dimension as f32;
-    let new_width = (width as f32 * scale).round() as u32;
-    let new_height = (height as f32 * scale).round() as u32;
+    // never round a side down to zero pixels
+    let new_width = ((width as f32 * scale).round() as u32).max(1);
+    let new_height = ((height as f32 * scale).round() as u32).max(1);
 
     (new_width, new_height)
 }
@@ -19,8 +21,11 @@ pub fn bilinear_resize(
     src_height: u32,
     dst_width: u32,
     dst_height: u32,
-) -> Vec<u8> {
-    let mut dst = vec![0u8; (dst_width * dst_height) as usize];
+) -> Result<Vec<u8>, SiftError> {
+    check_buffer_len("image buffer", src.len(), src_width, src_height, 1)?;
+    let dst_len = pixel_count(dst_width, dst_height)?;
+
+    let mut dst = vec![0u8; dst_len];
 
     let x_ratio = src_width as f32 / dst_width as f32;
     let y_ratio = src_height as f32 / dst_height as f32;
@@ -54,5 +59,5 @@ pub fn bilinear_resize(
         }
     }
 
-    dst
+    Ok(dst)
 }
@@ -0,0 +1,276 @@
+// Randomised kd-forest for approximate nearest-neighbour search over
+// descriptors (Silpa-Anan & Hartley, as used by FLANN). Every tree splits on a
+// dimension picked at random among the few with the highest variance, so the
+// trees partition the space differently; a single priority queue of unexplored
+// branches is shared across all trees and the search stops after `checks`
+// descriptors have been compared.
+
+use crate::distance::DescriptorValue;
+use crate::rng::Rng;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// split dimension is drawn among this many highest-variance dimensions
+const RAND_DIMS: usize = 5;
+// mean / variance are estimated from at most this many points per node
+const SAMPLE_SIZE: usize = 100;
+const LEAF_SIZE: usize = 4;
+
+enum Node {
+    Leaf { start: usize, end: usize },
+    Split { dim: usize, value: f32, left: usize, right: usize },
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    indices: Vec<usize>, // leaves refer to ranges of this permutation
+}
+
+pub struct KdForest<'a, T> {
+    data: &'a [T],
+    d: usize,
+    trees: Vec<Tree>,
+}
+
+// Unexplored branch, ordered so BinaryHeap pops the closest one first
+struct Branch {
+    dist: f32,
+    tree: usize,
+    node: usize,
+}
+
+impl PartialEq for Branch {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Branch {}
+impl PartialOrd for Branch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Branch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.dist.total_cmp(&self.dist)
+    }
+}
+
+// Reusable per-query state, so searching does not allocate per descriptor
+pub struct Searcher {
+    heap: BinaryHeap<Branch>,
+    visited: Vec<u32>, // query stamp per point, to skip points seen in another tree
+    stamp: u32,
+    exhaustive: bool, // the budget covers every point, so nothing is pruned
+}
+
+impl<'a, T: DescriptorValue> KdForest<'a, T> {
+    // Builds `num_trees` trees over the `data.len() / d` rows of `data`
+    pub fn build(data: &'a [T], d: usize, num_trees: usize, seed: u64) -> Self {
+        let n = data.len() / d;
+        let mut rng = Rng::new(seed);
+        let trees = (0..num_trees.max(1))
+            .map(|_| {
+                let mut tree = Tree {
+                    nodes: Vec::new(),
+                    indices: (0..n).collect(),
+                };
+                build_node(&mut tree, data, d, 0, n, &mut rng);
+                tree
+            })
+            .collect();
+        KdForest { data, d, trees }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.d
+    }
+
+    pub fn searcher(&self) -> Searcher {
+        Searcher {
+            heap: BinaryHeap::new(),
+            visited: vec![0; self.len()],
+            stamp: 0,
+            exhaustive: false,
+        }
+    }
+
+    fn row(&self, i: usize) -> &[T] {
+        &self.data[i * self.d..(i + 1) * self.d]
+    }
+
+    // Approximate two nearest neighbours of `query` after comparing at most
+    // `checks` descriptors. Branch distances accumulate the per-split margins
+    // as in FLANN, which is not a strict lower bound, so pruning on them may
+    // miss the true neighbour. A budget of at least `len()` therefore turns
+    // pruning off: every point is compared and the result is exact.
+    // Returns (index of best, best squared distance, second squared distance).
+    pub fn best_two(
+        &self,
+        query: &[T],
+        checks: usize,
+        s: &mut Searcher,
+    ) -> Option<(usize, f32, f32)> {
+        if self.len() == 0 {
+            return None;
+        }
+        s.heap.clear();
+        s.exhaustive = checks >= self.len();
+        s.stamp = s.stamp.wrapping_add(1);
+        if s.stamp == 0 {
+            s.visited.iter_mut().for_each(|v| *v = 0);
+            s.stamp = 1;
+        }
+
+        let mut best = (usize::MAX, f32::INFINITY, f32::INFINITY);
+        let mut checked = 0usize;
+
+        for tree in 0..self.trees.len() {
+            self.descend(tree, 0, 0.0, query, &mut best, &mut checked, s);
+        }
+        while checked < checks {
+            let Some(branch) = s.heap.pop() else { break };
+            // the bound only grows along the queue, nothing closer is left
+            if !s.exhaustive && branch.dist >= best.2 {
+                break;
+            }
+            self.descend(branch.tree, branch.node, branch.dist, query, &mut best, &mut checked, s);
+        }
+        Some(best)
+    }
+
+    // Follows the query down to a leaf, queueing the far side of every split
+    #[allow(clippy::too_many_arguments)]
+    fn descend(
+        &self,
+        tree: usize,
+        mut node: usize,
+        min_dist: f32,
+        query: &[T],
+        best: &mut (usize, f32, f32),
+        checked: &mut usize,
+        s: &mut Searcher,
+    ) {
+        let t = &self.trees[tree];
+        loop {
+            match t.nodes[node] {
+                Node::Split { dim, value, left, right } => {
+                    let diff = query[dim].to_f32() - value;
+                    let (near, far) = if diff < 0.0 { (left, right) } else { (right, left) };
+                    let far_dist = min_dist + diff * diff;
+                    if s.exhaustive || far_dist < best.2 {
+                        s.heap.push(Branch { dist: far_dist, tree, node: far });
+                    }
+                    node = near;
+                }
+                Node::Leaf { start, end } => {
+                    for &i in &t.indices[start..end] {
+                        if s.visited[i] == s.stamp {
+                            continue;
+                        }
+                        s.visited[i] = s.stamp;
+                        *checked += 1;
+                        let dist = T::l2_sq(query, self.row(i));
+                        if dist < best.1 {
+                            *best = (i, dist, best.1);
+                        } else if dist < best.2 {
+                            best.2 = dist;
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Recursively splits tree.indices[start..end], returning the new node's index
+fn build_node<T: DescriptorValue>(
+    tree: &mut Tree,
+    data: &[T],
+    d: usize,
+    start: usize,
+    end: usize,
+    rng: &mut Rng,
+) -> usize {
+    let id = tree.nodes.len();
+    if end - start <= LEAF_SIZE {
+        tree.nodes.push(Node::Leaf { start, end });
+        return id;
+    }
+
+    // mean and variance per dimension over a prefix sample of the node
+    let count = (end - start).min(SAMPLE_SIZE);
+    let sample = &tree.indices[start..start + count];
+    let mut mean = vec![0.0f32; d];
+    for &i in sample {
+        for (m, v) in mean.iter_mut().zip(&data[i * d..(i + 1) * d]) {
+            *m += v.to_f32();
+        }
+    }
+    mean.iter_mut().for_each(|m| *m /= count as f32);
+    let mut var = vec![0.0f32; d];
+    for &i in sample {
+        for ((acc, v), m) in var.iter_mut().zip(&data[i * d..(i + 1) * d]).zip(&mean) {
+            *acc += (v.to_f32() - m) * (v.to_f32() - m);
+        }
+    }
+
+    let mut dims: Vec<usize> = (0..d).collect();
+    dims.sort_by(|&a, &b| var[b].total_cmp(&var[a]));
+    let dim = dims[rng.below(RAND_DIMS.min(d))];
+    let mut value = mean[dim];
+
+    // partition around the mean; fall back to a median split if one side is
+    // empty (the mean came from a sample). Either way left <= value <= right.
+    let slice = &mut tree.indices[start..end];
+    let mut lo = 0;
+    for k in 0..slice.len() {
+        if data[slice[k] * d + dim].to_f32() < value {
+            slice.swap(lo, k);
+            lo += 1;
+        }
+    }
+    if lo == 0 || lo == slice.len() {
+        lo = slice.len() / 2;
+        slice.select_nth_unstable_by(lo, |&a, &b| {
+            data[a * d + dim].to_f32().total_cmp(&data[b * d + dim].to_f32())
+        });
+        value = data[slice[lo] * d + dim].to_f32();
+    }
+    let mid = start + lo;
+
+    tree.nodes.push(Node::Leaf { start, end }); // placeholder until children exist
+    let left = build_node(tree, data, d, start, mid, rng);
+    let right = build_node(tree, data, d, mid, end, rng);
+    tree.nodes[id] = Node::Split { dim, value, left, right };
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::best_two_batch;
+
+    // n random rows of d values in [0, 1)
+    fn random_rows(n: usize, d: usize, seed: u64) -> Vec<f32> {
+        let mut rng = Rng::new(seed);
+        (0..n * d).map(|_| rng.unit_f32()).collect()
+    }
+
+    #[test]
+    fn unlimited_checks_find_the_exact_neighbours() {
+        // a single tree over few dimensions splits the same dimension
+        // repeatedly, where the accumulated bound overestimates the most
+        let d = 2;
+        let data = random_rows(500, d, 1);
+        let queries = random_rows(200, d, 2);
+        let forest = KdForest::build(&data, d, 1, 9);
+        let mut searcher = forest.searcher();
+        let exact = best_two_batch(&queries, &data, d);
+        for (q, expected) in queries.chunks_exact(d).zip(exact) {
+            let found = forest.best_two(q, usize::MAX, &mut searcher);
+            assert_eq!(found, expected);
+        }
+    }
+}
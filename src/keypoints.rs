@@ -1,53 +1,106 @@
+use crate::config::{DescriptorNorm, SiftConfig};
+use crate::error::SiftError;
 use crate::grid::Grid;
+use crate::par;
+use js_sys::{Float32Array, Int32Array, Uint32Array};
+use wasm_bindgen::prelude::*;
+
+// Number of f32 values per keypoint in the flat layout of `flatten_keypoints`
+pub const KEYPOINT_STRIDE: usize = 6;
+
+/// Offsets of the fields inside one `KEYPOINT_STRIDE`-long record of the
+/// flat `SiftResult::keypoints` array; see also `keypoint_stride()`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeypointField {
+    X = 0,
+    Y = 1,
+    Octave = 2,
+    Level = 3,
+    Sigma = 4,
+    Angle = 5,
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const KEYPOINT_RECORD_TS: &'static str = r#"
+/** One record of the flat `SiftResult.keypoints` array (stride `keypoint_stride()`). */
+export type KeypointRecord = [x: number, y: number, octave: number, level: number, sigma: number, angle: number];
+"#;
 
 #[derive(Clone, Debug)]
 pub struct Keypoint {
+    // position and scale in input-image pixels
     x: f32,
     y: f32,
-    octave: usize,
-    level: usize,
     sigma: f32,
+    octave: i32, // -1 for the upsampled first octave
+    level: usize,
     angle: f32,
+    response: f32, // |interpolated DoG value| at the extremum
+    // position and scale inside the octave's own (downsampled) grid
+    octave_x: f32,
+    octave_y: f32,
+    octave_sigma: f32,
 }
 
-fn kernel_dxx() -> Grid<f32> {
-    // [[0, 0, 0], [1, -2, 1], [0, 0, 0]]
-    Grid::new(&[0.0, 0.0, 0.0, 1.0, -2.0, 1.0, 0.0, 0.0, 0.0], 3, 3)
-}
+impl Keypoint {
+    // Builds a keypoint from its image-space values; the octave-local ones
+    // follow from the octave
+    pub fn new(x: f32, y: f32, sigma: f32, angle: f32, octave: i32, level: usize, response: f32) -> Self {
+        let scale = octave_scale(octave);
+        Keypoint {
+            x,
+            y,
+            sigma,
+            octave,
+            level,
+            angle,
+            response,
+            octave_x: x / scale,
+            octave_y: y / scale,
+            octave_sigma: sigma / scale,
+        }
+    }
 
-fn kernel_dyy() -> Grid<f32> {
-    // [[0, 1, 0], [0, -2, 0], [0, 1, 0]]
-    Grid::new(&[0.0, 1.0, 0.0, 0.0, -2.0, 0.0, 0.0, 1.0, 0.0], 3, 3)
+    pub fn x(&self) -> f32 {
+        self.x
+    }
+    pub fn y(&self) -> f32 {
+        self.y
+    }
+    pub fn sigma(&self) -> f32 {
+        self.sigma
+    }
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+    pub fn octave(&self) -> i32 {
+        self.octave
+    }
+    pub fn level(&self) -> usize {
+        self.level
+    }
+    pub fn response(&self) -> f32 {
+        self.response
+    }
 }
 
-fn kernel_dxy() -> Grid<f32> {
-    Grid::new(&[0.25, 0.0, -0.25, 0.0, 0.0, 0.0, -0.25, 0.0, 0.25], 3, 3)
+#[inline]
+fn octave_scale(octave: i32) -> f32 {
+    // factor that maps octave-local pixels to input-image pixels
+    2.0_f32.powi(octave)
 }
 
-fn hessian_terms(dog: &Grid<f32>) -> (Grid<f32>, Grid<f32>, Grid<f32>) {
-    // computes the three second-order derivative images
-    let dxx = dog.convolve(&kernel_dxx());
-    let dyy = dog.convolve(&kernel_dyy());
-    let dxy = dog.convolve(&kernel_dxy());
-    (dxx, dyy, dxy)
-}
+// Maximum number of quadratic fitting steps before a candidate is dropped
+const MAX_INTERP_STEPS: usize = 5;
+// Secondary orientation peaks at or above this fraction of the maximum spawn their own keypoint
+const ORI_PEAK_RATIO: f32 = 0.8;
 
-fn pass_edge_response(
-    dxx: &Grid<f32>,
-    dyy: &Grid<f32>,
-    dxy: &Grid<f32>,
-    x: u32,
-    y: u32,
-    r: f32,
-) -> bool {
+fn pass_edge_response(dxx: f32, dyy: f32, dxy: f32, r: f32) -> bool {
     // rejects keypoints that lie on edges by checking
     // the ratio of principal curvatures of the local Hessian
-    let gradient_xx = dxx.get_pixel(x, y);
-    let gradient_yy = dyy.get_pixel(x, y);
-    let gradient_xy = dxy.get_pixel(x, y);
-
-    let tr = gradient_xx + gradient_yy;
-    let det = gradient_xx * gradient_yy - gradient_xy * gradient_xy;
+    let tr = dxx + dyy;
+    let det = dxx * dyy - dxy * dxy;
     if det <= 0.0 {
         return false;
     }
@@ -56,15 +109,129 @@ fn pass_edge_response(
     edge_ratio < r_cond
 }
 
+fn solve_3x3(h: [[f32; 3]; 3], b: [f32; 3]) -> Option<[f32; 3]> {
+    // solves h * x = b with Cramer's rule, None if h is (near) singular
+    let det = h[0][0] * (h[1][1] * h[2][2] - h[1][2] * h[2][1])
+        - h[0][1] * (h[1][0] * h[2][2] - h[1][2] * h[2][0])
+        + h[0][2] * (h[1][0] * h[2][1] - h[1][1] * h[2][0]);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let mut x = [0.0f32; 3];
+    for (col, out) in x.iter_mut().enumerate() {
+        let mut m = h;
+        for row in 0..3 {
+            m[row][col] = b[row];
+        }
+        let det_col = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        *out = det_col / det;
+    }
+    Some(x)
+}
 
+// Result of fitting a 3D quadratic around a DoG extremum
+struct Refined {
+    x: u32,
+    y: u32,
+    level: usize,
+    offset: [f32; 3], // sub-pixel / sub-level offset (x, y, s) from the integer sample
+    value: f32,       // interpolated DoG value at the offset
+}
+
+fn refine_extremum(
+    dogs_octave: &[Grid<f32>],
+    level: usize,
+    x: u32,
+    y: u32,
+    scales: usize,
+    contrast_thresh: f32,
+    edge_r: f32,
+) -> Option<Refined> {
+    // Lowe's iterative refinement: fit a quadratic to the DoG stack around
+    // (x, y, s), step to the neighbouring sample while the offset exceeds half
+    // a sample in any dimension, and reject unstable or low-contrast points.
+    let width = dogs_octave[level].get_width() as i32;
+    let height = dogs_octave[level].get_height() as i32;
+    let (mut xi, mut yi, mut si) = (x as i32, y as i32, level as i32);
+
+    for _ in 0..MAX_INTERP_STEPS {
+        let prev = &dogs_octave[(si - 1) as usize];
+        let curr = &dogs_octave[si as usize];
+        let next = &dogs_octave[(si + 1) as usize];
+
+        let v = curr.get_pixel_safe(xi, yi);
+
+        // first derivatives by central differences
+        let dx = (curr.get_pixel_safe(xi + 1, yi) - curr.get_pixel_safe(xi - 1, yi)) * 0.5;
+        let dy = (curr.get_pixel_safe(xi, yi + 1) - curr.get_pixel_safe(xi, yi - 1)) * 0.5;
+        let ds = (next.get_pixel_safe(xi, yi) - prev.get_pixel_safe(xi, yi)) * 0.5;
+
+        // second derivatives
+        let dxx = curr.get_pixel_safe(xi + 1, yi) + curr.get_pixel_safe(xi - 1, yi) - 2.0 * v;
+        let dyy = curr.get_pixel_safe(xi, yi + 1) + curr.get_pixel_safe(xi, yi - 1) - 2.0 * v;
+        let dss = next.get_pixel_safe(xi, yi) + prev.get_pixel_safe(xi, yi) - 2.0 * v;
+        let dxy = (curr.get_pixel_safe(xi + 1, yi + 1) - curr.get_pixel_safe(xi - 1, yi + 1)
+            - curr.get_pixel_safe(xi + 1, yi - 1)
+            + curr.get_pixel_safe(xi - 1, yi - 1))
+            * 0.25;
+        let dxs = (next.get_pixel_safe(xi + 1, yi) - next.get_pixel_safe(xi - 1, yi)
+            - prev.get_pixel_safe(xi + 1, yi)
+            + prev.get_pixel_safe(xi - 1, yi))
+            * 0.25;
+        let dys = (next.get_pixel_safe(xi, yi + 1) - next.get_pixel_safe(xi, yi - 1)
+            - prev.get_pixel_safe(xi, yi + 1)
+            + prev.get_pixel_safe(xi, yi - 1))
+            * 0.25;
+
+        let hessian = [[dxx, dxy, dxs], [dxy, dyy, dys], [dxs, dys, dss]];
+        let gradient = [dx, dy, ds];
+        let offset = solve_3x3(hessian, [-dx, -dy, -ds])?;
+        if !offset.iter().all(|o| o.is_finite()) {
+            return None;
+        }
+
+        // Converged: the extremum lies within half a sample of (xi, yi, si)
+        if offset.iter().all(|o| o.abs() < 0.5) {
+            let value = v + 0.5 * (gradient[0] * offset[0] + gradient[1] * offset[1] + gradient[2] * offset[2]);
+            if value.abs() < contrast_thresh {
+                return None;
+            }
+            if !pass_edge_response(dxx, dyy, dxy, edge_r) {
+                return None;
+            }
+            return Some(Refined {
+                x: xi as u32,
+                y: yi as u32,
+                level: si as usize,
+                offset,
+                value,
+            });
+        }
+
+        // Otherwise move to the neighbouring sample and fit again
+        xi += offset[0].round() as i32;
+        yi += offset[1].round() as i32;
+        si += offset[2].round() as i32;
+
+        if si < 1 || si > scales as i32 || xi < 1 || yi < 1 || xi >= width - 1 || yi >= height - 1 {
+            return None;
+        }
+    }
+
+    // Did not converge within MAX_INTERP_STEPS
+    None
+}
 
 #[inline]
-fn sigma_for_level(sigma0: f32, k: f32, level: usize) -> f32 {
-    // compute the scale at the given level
-    sigma0 * k.powi(level as i32)
+fn sigma_for_level(sigma0: f32, k: f32, level: f32) -> f32 {
+    // compute the scale at the given (possibly fractional) level
+    sigma0 * k.powf(level)
 }
-fn assign_orientation(gaussian: &Grid<f32>, x: u32, y: u32, sigma: f32) -> f32 {
-    // This function computes the dominant orientation for a keypoint
+fn assign_orientations(gaussian: &Grid<f32>, x: u32, y: u32, sigma: f32) -> Vec<f32> {
+    // This function computes the dominant orientations for a keypoint: the
+    // histogram maximum plus every local peak within ORI_PEAK_RATIO of it
 
     // Number of orientation histogram bins
     let bins = 36usize;
@@ -123,17 +290,38 @@ fn assign_orientation(gaussian: &Grid<f32>, x: u32, y: u32, sigma: f32) -> f32 {
         }
     }
 
-    // Find bin with highest peak
-    let (mut max_bin, mut max_val) = (0usize, -1.0f32);
-    for (i, &v) in hist.iter().enumerate() {
-        if v > max_val {
-            max_val = v;
-            max_bin = i;
-        }
+    // Smooth the circular histogram with a [1, 4, 6, 4, 1] / 16 kernel
+    let smoothed: Vec<f32> = (0..bins)
+        .map(|i| {
+            let at = |offset: isize| hist[(i as isize + offset).rem_euclid(bins as isize) as usize];
+            (at(-2) + at(2)) * (1.0 / 16.0) + (at(-1) + at(1)) * (4.0 / 16.0) + at(0) * (6.0 / 16.0)
+        })
+        .collect();
+
+    let max_val = smoothed.iter().cloned().fold(0.0f32, f32::max);
+    if max_val <= 0.0 {
+        // flat patch, no gradient at all: fall back to a single zero orientation
+        return vec![0.0];
     }
 
-    // Convert max bin index to angle in radians
-    (max_bin as f32 + 0.5) * (std::f32::consts::TAU / bins as f32)
+    let mut angles = Vec::new();
+    for i in 0..bins {
+        let left = smoothed[(i + bins - 1) % bins];
+        let right = smoothed[(i + 1) % bins];
+        let center = smoothed[i];
+
+        // Keep local peaks that are close enough to the global maximum
+        if center > left && center > right && center >= ORI_PEAK_RATIO * max_val {
+            // Parabolic interpolation of the peak position between neighbouring bins
+            let denom = left - 2.0 * center + right;
+            let shift = if denom != 0.0 { 0.5 * (left - right) / denom } else { 0.0 };
+            let bin = (i as f32 + shift).rem_euclid(bins as f32);
+
+            // Convert bin position to angle in radians (bin i is centred on i * 360 / bins)
+            angles.push(wrap_angle_2pi(bin * std::f32::consts::TAU / bins as f32));
+        }
+    }
+    angles
 }
 fn is_local_extremum(
     dogs_octave: &[Grid<f32>],
@@ -159,8 +347,7 @@ fn is_local_extremum(
     let yi = y as i32;
 
     // Check 26 neighbors (3x3x3 cube excluding center)
-    for ds in (s - 1)..=(s + 1) {         // Scale dimension
-        let img = &dogs_octave[ds];
+    for (ds, img) in dogs_octave.iter().enumerate().skip(s - 1).take(3) { // Scale dimension
         for dy in -1..=1 {                 // Y dimension  
             for dx in -1..=1 {             // X dimension
                 // Skip comparison with center pixel itself
@@ -193,67 +380,222 @@ fn is_local_extremum(
 pub fn detect_keypoints(
     dogs: &[Vec<Grid<f32>>],
     gaussians: &[Vec<Grid<f32>>],
-    scales: usize,
-    sigma0: f32,
-    contrast_thresh: f32,
-    edge_r: f32,
-    k: f32,
+    config: &SiftConfig,
+    masks: Option<&[Grid<u8>]>,
 ) -> Vec<Keypoint> {
-    // Vector to store detected keypoints
-    let mut keypoints = Vec::new();
+    let scales = config.scales();
+    let sigma0 = config.sigma0();
+    let contrast_thresh = config.contrast_thresh();
+    let edge_r = config.edge_r();
+    let k = config.k();
+    let first_octave = config.first_octave();
 
-    // Iterate through each octave in the DoG pyramid
-    for (octave, dogs_octave) in dogs.iter().enumerate() {
+    // One task per interior row of every scanned DoG level, in scan order, so
+    // rows can be processed in parallel and concatenated deterministically
+    let mut rows = Vec::new();
+    for (octave_index, dogs_octave) in dogs.iter().enumerate() {
         // Process each scale level except first and last
-        for scale_level in 1..=scales {
-            let difference_of_gaussian = &dogs_octave[scale_level];
-            let image_width = difference_of_gaussian.get_width();
-            let image_height = difference_of_gaussian.get_height();
+        for (scale_level, dog) in dogs_octave.iter().enumerate().take(scales + 1).skip(1) {
+            let image_width = dog.get_width();
+            let image_height = dog.get_height();
 
             // Skip if image is too small
             if image_width < 3 || image_height < 3 {
                 continue;
             }
+            rows.extend((1..(image_height - 1)).map(|y| (octave_index, scale_level, y)));
+        }
+    }
 
-            // Build Hessian maps once for this level for edge response testing
-            let (hessian_xx, hessian_yy, hessian_xy) = hessian_terms(difference_of_gaussian);
+    let per_row = par::map_slice(&rows, |&(octave_index, scale_level, y_coord)| {
+        let dogs_octave = &dogs[octave_index];
+        let octave = octave_index as i32 + first_octave;
+        let mask = masks.map(|m| &m[octave_index]);
+        let image_width = dogs_octave[scale_level].get_width();
 
-            // Scan interior pixels of the image (exclude borders)
-            for y_coord in 1..(image_height - 1) {
-                for x_coord in 1..(image_width - 1) {
-                    // Check if point is local extremum with sufficient contrast
-                    if !is_local_extremum(dogs_octave, scale_level, x_coord, y_coord, contrast_thresh) {
-                        continue;
-                    }
+        // Scan interior pixels of the row (exclude borders)
+        let mut keypoints = Vec::new();
+        for x_coord in 1..(image_width - 1) {
+            // Skip samples on excluded mask pixels
+            if mask.is_some_and(|m| m.get_pixel(x_coord, y_coord) == 0) {
+                continue;
+            }
 
-                    // Check if point passes edge response test
-                    if !pass_edge_response(&hessian_xx, &hessian_yy, &hessian_xy, x_coord, y_coord, edge_r) {
-                        continue;
-                    }
+            // Check if point is local extremum; the raw sample only has to pass half
+            // the contrast threshold, the full one is applied after interpolation
+            if !is_local_extremum(dogs_octave, scale_level, x_coord, y_coord, 0.5 * contrast_thresh) {
+                continue;
+            }
 
-                    // Calculate scale and orientation for valid keypoint
-                    let keypoint_sigma = sigma_for_level(sigma0, k, scale_level);
-                    let keypoint_angle = assign_orientation(&gaussians[octave][scale_level], x_coord, y_coord, keypoint_sigma);
-
-                    // Store the keypoint
-                    keypoints.push(Keypoint {
-                        x: x_coord as f32,
-                        y: y_coord as f32,
-                        octave: octave,
-                        level: scale_level,
-                        sigma: keypoint_sigma,
-                        angle: keypoint_angle,
-                    });
-                }
+            // Refine to sub-pixel / sub-level accuracy, then test contrast and edge response
+            let Some(refined) = refine_extremum(
+                dogs_octave,
+                scale_level,
+                x_coord,
+                y_coord,
+                scales,
+                contrast_thresh,
+                edge_r,
+            ) else {
+                continue;
+            };
+
+            let octave_x = refined.x as f32 + refined.offset[0];
+            let octave_y = refined.y as f32 + refined.offset[1];
+
+            // Refinement may have moved the extremum onto an excluded pixel
+            if mask.is_some_and(|m| {
+                m.get_pixel_safe(octave_x.round() as i32, octave_y.round() as i32) == 0
+            }) {
+                continue;
             }
+
+            // Calculate scale and orientations for valid keypoint
+            let keypoint_sigma = sigma_for_level(sigma0, k, refined.level as f32 + refined.offset[2]);
+            let keypoint_angles = assign_orientations(
+                &gaussians[octave_index][refined.level],
+                octave_x.round() as u32,
+                octave_y.round() as u32,
+                keypoint_sigma,
+            );
+
+            // Store one keypoint per dominant orientation in image space,
+            // keeping the octave-local values
+            let scale = octave_scale(octave);
+            for keypoint_angle in keypoint_angles {
+                keypoints.push(Keypoint {
+                    x: octave_x * scale,
+                    y: octave_y * scale,
+                    sigma: keypoint_sigma * scale,
+                    octave,
+                    level: refined.level,
+                    angle: keypoint_angle,
+                    response: refined.value.abs(),
+                    octave_x,
+                    octave_y,
+                    octave_sigma: keypoint_sigma,
+                });
+            }
+        }
+        keypoints
+    });
+    per_row.into_iter().flatten().collect()
+}
+pub fn retain_strongest(kps: &mut Vec<Keypoint>, max_features: usize) {
+    // keeps the `max_features` keypoints with the largest response; ties keep
+    // the earlier keypoint and the survivors stay in detection order
+    if kps.len() <= max_features {
+        return;
+    }
+    let mut order: Vec<usize> = (0..kps.len()).collect();
+    order.sort_by(|&a, &b| kps[b].response.total_cmp(&kps[a].response));
+    let mut keep = vec![false; kps.len()];
+    for &i in &order[..max_features] {
+        keep[i] = true;
+    }
+    let mut flags = keep.into_iter();
+    kps.retain(|_| flags.next().unwrap_or(false));
+}
+
+pub fn keypoints_from_flat(
+    flat: &[f32],
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+    num_octaves: usize,
+    derive_levels: bool,
+) -> Result<Vec<Keypoint>, SiftError> {
+    // Rebuilds keypoints from the flat layout of `flatten_keypoints` so they can
+    // be described. With `derive_levels` only x, y, sigma and angle are used and
+    // the octave and level are recomputed from sigma, so keypoints from other
+    // detectors may leave those two fields at 0; otherwise the octave and level
+    // written by the detector are used as-is. Sigma may not exceed the larger
+    // image side: the descriptor window would then span the image many times.
+    if !flat.len().is_multiple_of(KEYPOINT_STRIDE) {
+        return Err(SiftError::BufferLength {
+            name: "keypoints",
+            expected: flat.len() / KEYPOINT_STRIDE * KEYPOINT_STRIDE,
+            actual: flat.len(),
+        });
+    }
+    if num_octaves == 0 {
+        return Err(SiftError::invalid_parameter("image", "too small for a single octave"));
+    }
+    let scales = config.scales();
+    let first_octave = config.first_octave();
+    let last_octave = first_octave + num_octaves as i32 - 1;
+
+    let mut out = Vec::with_capacity(flat.len() / KEYPOINT_STRIDE);
+    for (i, rec) in flat.chunks_exact(KEYPOINT_STRIDE).enumerate() {
+        let (x, y, sigma, angle) = (rec[0], rec[1], rec[4], rec[5]);
+        if !(x.is_finite() && y.is_finite() && angle.is_finite() && sigma.is_finite() && sigma > 0.0) {
+            return Err(SiftError::invalid_parameter(
+                "keypoints",
+                format!("keypoint {} has a non-finite value or sigma <= 0", i),
+            ));
+        }
+        if sigma > width.max(height) as f32 {
+            return Err(SiftError::invalid_parameter(
+                "keypoints",
+                format!("keypoint {} has sigma {} beyond the {}x{} image", i, sigma, width, height),
+            ));
         }
+
+        let (octave, level) = if derive_levels {
+            octave_level_for_sigma(sigma, config, last_octave)
+        } else {
+            let (octave, level) = (rec[2], rec[3]);
+            if octave.fract() != 0.0
+                || level.fract() != 0.0
+                || octave < first_octave as f32
+                || octave > last_octave as f32
+                || level < 0.0
+                || level > (scales + 2) as f32
+            {
+                return Err(SiftError::invalid_parameter(
+                    "keypoints",
+                    format!(
+                        "keypoint {} has octave {} / level {} outside the pyramid (octaves {}..={}, levels 0..={})",
+                        i,
+                        octave,
+                        level,
+                        first_octave,
+                        last_octave,
+                        scales + 2
+                    ),
+                ));
+            }
+            (octave as i32, level as usize)
+        };
+
+        out.push(Keypoint::new(x, y, sigma, angle, octave, level, 0.0));
+    }
+    Ok(out)
+}
+
+// Octave and nearest level of a keypoint of scale `sigma` (image pixels), from
+// sigma = sigma0 * 2^(octave + level / scales), clamped to the first octave of
+// `config` and to `last_octave`
+pub fn octave_level_for_sigma(sigma: f32, config: &SiftConfig, last_octave: i32) -> (i32, usize) {
+    let scales = config.scales();
+    let first_octave = config.first_octave();
+    let t = (sigma / config.sigma0()).log2();
+    let mut octave = t.floor() as i32;
+    let mut level = ((t - octave as f32) * scales as f32).round() as i64;
+    if octave < first_octave {
+        octave = first_octave;
+        level = 0;
+    } else if octave > last_octave {
+        octave = last_octave;
+        level = scales as i64;
     }
-    keypoints
+    (octave, level.clamp(0, scales as i64 + 2) as usize)
 }
+
 pub fn flatten_keypoints(kps: &[Keypoint]) -> Vec<f32> {
     // flattens keypoint to a vector of 6 floats: x, y, octave, level, sigma, angle
-    // for return to js
-    let mut out = Vec::with_capacity(kps.len() * 6);
+    // for return to js; x, y and sigma are in input-image pixels
+    let mut out = Vec::with_capacity(kps.len() * KEYPOINT_STRIDE);
     for kp in kps {
         out.push(kp.x);
         out.push(kp.y);
@@ -265,6 +607,131 @@ pub fn flatten_keypoints(kps: &[Keypoint]) -> Vec<f32> {
     out
 }
 
+/// Struct-of-arrays view of the detected keypoints, one entry per keypoint in
+/// the same order as `SiftResult::keypoints` and the descriptors.
+///
+/// Positions and scales are in input-image pixels; `responses` is the absolute
+/// interpolated DoG value, larger meaning more contrast.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct KeypointSet {
+    xs: Vec<f32>,
+    ys: Vec<f32>,
+    scales: Vec<f32>,
+    angles: Vec<f32>,
+    responses: Vec<f32>,
+    octaves: Vec<i32>,
+    levels: Vec<u32>,
+    len: usize, // kept apart from the columns, which the `take_` accessors empty
+}
+
+#[wasm_bindgen]
+impl KeypointSet {
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[wasm_bindgen(getter)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn xs(&self) -> Vec<f32> {
+        self.xs.clone()
+    }
+    pub fn ys(&self) -> Vec<f32> {
+        self.ys.clone()
+    }
+    pub fn scales(&self) -> Vec<f32> {
+        self.scales.clone()
+    }
+    pub fn angles(&self) -> Vec<f32> {
+        self.angles.clone()
+    }
+    pub fn responses(&self) -> Vec<f32> {
+        self.responses.clone()
+    }
+    pub fn octaves(&self) -> Vec<i32> {
+        self.octaves.clone()
+    }
+    pub fn levels(&self) -> Vec<u32> {
+        self.levels.clone()
+    }
+
+    /// Zero-copy view of `xs` into wasm memory, valid under the same rules as
+    /// `SiftResult::keypoints_view`. The other columns have `_view` and
+    /// `take_` accessors of their own.
+    pub fn xs_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.xs) }
+    }
+    pub fn ys_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.ys) }
+    }
+    pub fn scales_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.scales) }
+    }
+    pub fn angles_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.angles) }
+    }
+    pub fn responses_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.responses) }
+    }
+    pub fn octaves_view(&self) -> Int32Array {
+        unsafe { Int32Array::view(&self.octaves) }
+    }
+    pub fn levels_view(&self) -> Uint32Array {
+        unsafe { Uint32Array::view(&self.levels) }
+    }
+
+    /// Moves `xs` out without cloning it; afterwards the column is empty.
+    pub fn take_xs(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.xs)
+    }
+    pub fn take_ys(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.ys)
+    }
+    pub fn take_scales(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.scales)
+    }
+    pub fn take_angles(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.angles)
+    }
+    pub fn take_responses(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.responses)
+    }
+    pub fn take_octaves(&mut self) -> Vec<i32> {
+        std::mem::take(&mut self.octaves)
+    }
+    pub fn take_levels(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.levels)
+    }
+}
+
+impl KeypointSet {
+    pub fn from_keypoints(kps: &[Keypoint]) -> Self {
+        KeypointSet {
+            xs: kps.iter().map(|kp| kp.x).collect(),
+            ys: kps.iter().map(|kp| kp.y).collect(),
+            scales: kps.iter().map(|kp| kp.sigma).collect(),
+            angles: kps.iter().map(|kp| kp.angle).collect(),
+            responses: kps.iter().map(|kp| kp.response).collect(),
+            octaves: kps.iter().map(|kp| kp.octave).collect(),
+            levels: kps.iter().map(|kp| kp.level as u32).collect(),
+            len: kps.len(),
+        }
+    }
+}
+
+pub fn flatten_octave_coords(kps: &[Keypoint]) -> Vec<f32> {
+    // flattens the octave-local values to 3 floats per keypoint: x, y, sigma
+    let mut out = Vec::with_capacity(kps.len() * 3);
+    for kp in kps {
+        out.push(kp.octave_x);
+        out.push(kp.octave_y);
+        out.push(kp.octave_sigma);
+    }
+    out
+}
+
 fn wrap_angle_2pi(a: f32) -> f32 {
     let mut ang = a % std::f32::consts::TAU;
     if ang < 0.0 {
@@ -280,6 +747,8 @@ fn compute_descriptor_for(
     kp_y: f32,
     kp_sigma: f32,
     kp_angle: f32,
+    norm: DescriptorNorm,
+    clip_threshold: f32,
 ) -> [f32; 128] {
     // The SIFT descriptor creates a 128-dimensional vector that describes the local image region
     // It divides the region into a 4x4 grid of cells, with 8 orientation bins per cell (4x4x8 = 128 total values)
@@ -297,27 +766,24 @@ fn compute_descriptor_for(
     let sigma_descr: f32 = 0.5 * (N_CELLS as f32);
 
     // Calculate sample window radius in pixels to roughly cover all 4x4 cells
-    let radius: i32 = (kp_sigma * bin_size * (N_CELLS as f32) * 0.5 * 1.41421356).ceil() as i32;
+    let radius = (kp_sigma * bin_size * (N_CELLS as f32) * 0.5 * std::f32::consts::SQRT_2).ceil();
 
     // Initialize 3D histogram array [4x4x8] to accumulate orientation samples
     let mut hist = [0.0f32; DESC_LEN];
 
-    let width = gaussian.get_width() as i32;
-    let height = gaussian.get_height() as i32;
-    let keypoint_x = kp_x as i32;
-    let keypoint_y = kp_y as i32;
+    // Clip the window to the image interior (one pixel border for the central
+    // differences) in float, so a huge sigma or an off-image keypoint gives a
+    // bounded or empty loop instead of overflowing i32
+    let width = gaussian.get_width() as f32;
+    let height = gaussian.get_height() as f32;
+    let x_start = (kp_x.round() - radius).max(1.0) as i32;
+    let x_end = (kp_x.round() + radius).min(width - 2.0) as i32;
+    let y_start = (kp_y.round() - radius).max(1.0) as i32;
+    let y_end = (kp_y.round() + radius).min(height - 2.0) as i32;
 
     // Sample pixels in window around keypoint
-    for delta_y in -radius..=radius {
-        for delta_x in -radius..=radius {
-            let image_x = keypoint_x + delta_x;
-            let image_y = keypoint_y + delta_y;
-
-            // Skip pixels outside image boundaries
-            if image_x <= 0 || image_y <= 0 || image_x >= width - 1 || image_y >= height - 1 {
-                continue;
-            }
-
+    for image_y in y_start..=y_end {
+        for image_x in x_start..=x_end {
             // Calculate gradient magnitude and direction using central differences
             let gradient_x = gaussian.get_pixel_safe(image_x + 1, image_y) - gaussian.get_pixel_safe(image_x - 1, image_y);
             let gradient_y = gaussian.get_pixel_safe(image_x, image_y + 1) - gaussian.get_pixel_safe(image_x, image_y - 1);
@@ -327,8 +793,9 @@ fn compute_descriptor_for(
             }
 
             // Rotate and scale coordinates relative to keypoint orientation and scale
-            let delta_x_float = delta_x as f32;
-            let delta_y_float = delta_y as f32;
+            // measured from the sub-pixel keypoint centre
+            let delta_x_float = image_x as f32 - kp_x;
+            let delta_y_float = image_y as f32 - kp_y;
             let rotated_x = (cos_t * delta_x_float + sin_t * delta_y_float) / bin_size / kp_sigma;
             let rotated_y = (-sin_t * delta_x_float + cos_t * delta_y_float) / bin_size / kp_sigma;
 
@@ -389,47 +856,294 @@ fn compute_descriptor_for(
         }
     }
 
-    // Normalize descriptor vector for illumination invariance
-    // 1) First L2 normalization
-    let mut norm = 0.0f32;
-    for v in &hist {
-        norm += v * v;
-    }
-    norm = norm.sqrt();
+    normalize_descriptor(&mut hist, norm, clip_threshold);
+    hist
+}
+
+pub fn quantize_descriptors(desc: &[f32]) -> Vec<u8> {
+    // u8 descriptors as in VLFeat / OpenCV: scale by 512, round and saturate;
+    // normalised components rarely exceed 0.5, so little range is lost
+    desc.iter()
+        .map(|&v| (512.0 * v).round().clamp(0.0, 255.0) as u8)
+        .collect()
+}
+
+fn l2_normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
     if norm > 0.0 {
-        for v in &mut hist.iter_mut() {
-            *v /= norm;
-        }
+        v.iter_mut().for_each(|x| *x /= norm);
     }
-    // 2) Threshold values at 0.2 to reduce influence of large gradients
-    for v in &mut hist.iter_mut() {
-        if *v > 0.2 {
-            *v = 0.2;
+}
+
+fn normalize_descriptor(hist: &mut [f32], norm: DescriptorNorm, clip_threshold: f32) {
+    // Normalize descriptor vector for illumination invariance
+    match norm {
+        DescriptorNorm::ClippedL2 => {
+            // Lowe: L2 normalize, threshold values to reduce the influence of
+            // large gradients, then renormalize
+            l2_normalize(hist);
+            for v in hist.iter_mut() {
+                if *v > clip_threshold {
+                    *v = clip_threshold;
+                }
+            }
+            l2_normalize(hist);
         }
-    }
-    // 3) Renormalize again
-    norm = 0.0;
-    for v in &hist {
-        norm += v * v;
-    }
-    norm = norm.sqrt();
-    if norm > 0.0 {
-        for v in &mut hist.iter_mut() {
-            *v /= norm;
+        DescriptorNorm::L2 => l2_normalize(hist),
+        DescriptorNorm::RootSift => {
+            // Arandjelovic & Zisserman: L1 normalize, then element-wise sqrt, so
+            // the Euclidean distance compares like the Hellinger kernel. The
+            // result has unit L2 norm.
+            let l1 = hist.iter().map(|v| v.abs()).sum::<f32>();
+            if l1 > 0.0 {
+                hist.iter_mut().for_each(|v| *v = (*v / l1).sqrt());
+            }
         }
     }
-
-    hist
 }
 // Public: Extract 128D descriptors for a list of keypoints.
 // Returns a flat Vec<f32> of length 128 * keypoints.len(), in the same order.
-pub fn extract_descriptors(gaussians: &[Vec<Grid<f32>>], keypoints: &[Keypoint]) -> Vec<f32> {
-    let mut out = Vec::with_capacity(keypoints.len() * 128);
-    for kp in keypoints {
+pub fn extract_descriptors(
+    gaussians: &[Vec<Grid<f32>>],
+    keypoints: &[Keypoint],
+    config: &SiftConfig,
+) -> Vec<f32> {
+    let first_octave = config.first_octave();
+    let descs = par::map_slice(keypoints, |kp| {
         // Use the Gaussian image at the keypoint’s octave/level
-        let g = &gaussians[kp.octave][kp.level];
-        let desc = compute_descriptor_for(g, kp.x, kp.y, kp.sigma, kp.angle);
-        out.extend_from_slice(&desc);
+        let g = &gaussians[(kp.octave - first_octave) as usize][kp.level];
+        compute_descriptor_for(
+            g,
+            kp.octave_x,
+            kp.octave_y,
+            kp.octave_sigma,
+            kp.angle,
+            config.descriptor_norm(),
+            config.clip_threshold(),
+        )
+    });
+    let mut out = Vec::with_capacity(keypoints.len() * 128);
+    for desc in &descs {
+        out.extend_from_slice(desc);
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dark Gaussian blobs (x, y, sigma) on a light background
+    fn blob_image(width: u32, height: u32, blobs: &[(f32, f32, f32)]) -> Vec<u8> {
+        let mut image = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let darkness: f32 = blobs
+                    .iter()
+                    .map(|&(bx, by, s)| {
+                        let d2 = (x as f32 - bx).powi(2) + (y as f32 - by).powi(2);
+                        (-d2 / (2.0 * s * s)).exp()
+                    })
+                    .sum();
+                image.push((220.0 - 180.0 * darkness.min(1.0)).round() as u8);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn descriptors_for_oversized_and_off_image_keypoints() {
+        let (w, h) = (96, 96);
+        let image = blob_image(w, h, &[(30.0, 40.0, 4.0), (65.0, 60.0, 6.0)]);
+        let config = SiftConfig::default();
+        // octave 0, level 1 as written by the detector
+        let kp = |x: f32, y: f32, sigma: f32| [x, y, 0.0, 1.0, sigma, 0.3];
+
+        // far off the image the clipped window is empty: an all-zero descriptor
+        let desc = crate::run_describe(&image, w, h, &kp(1e9, -1e9, 2.0), &config, false).unwrap();
+        assert_eq!(desc.len(), 128);
+        assert!(desc.iter().all(|&v| v == 0.0));
+
+        // the largest accepted sigma covers the whole image and still completes
+        let desc = crate::run_describe(&image, w, h, &kp(48.0, 48.0, 96.0), &config, false).unwrap();
+        assert!(desc.iter().any(|&v| v > 0.0));
+
+        // beyond the image size sigma is rejected instead of scanning a huge window
+        assert!(matches!(
+            crate::run_describe(&image, w, h, &kp(48.0, 48.0, 1e30), &config, false),
+            Err(SiftError::InvalidParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn blob_centre_is_refined_to_sub_pixel_accuracy() {
+        let (w, h) = (96, 96);
+        let centre = (40.3, 35.6);
+        let image = blob_image(w, h, &[(centre.0, centre.1, 3.0)]);
+        let (_, kps) = crate::run_detect(&image, w, h, &SiftConfig::default(), None).unwrap();
+        let error = kps
+            .iter()
+            .map(|kp| ((kp.x - centre.0).powi(2) + (kp.y - centre.1).powi(2)).sqrt())
+            .fold(f32::INFINITY, f32::min);
+        // the integer sample alone would be off by about 0.5 px
+        assert!(error < 0.1, "closest keypoint is {} px off", error);
+    }
+
+    fn grid_from(size: u32, f: impl Fn(f32, f32) -> f32) -> Grid<f32> {
+        let data: Vec<f32> = (0..size * size).map(|i| f((i % size) as f32, (i / size) as f32)).collect();
+        Grid::new(&data, size, size)
+    }
+
+    fn angle_diff(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(std::f32::consts::TAU);
+        d.min(std::f32::consts::TAU - d)
+    }
+
+    #[test]
+    fn orientation_peak_is_interpolated_between_bins() {
+        // gradients fanning out symmetrically around 0.3 rad, which lies
+        // between the 10-degree bins at 0.175 and 0.349
+        let theta = 0.3f32;
+        let (c, s) = (theta.cos(), theta.sin());
+        let grid = grid_from(64, |x, y| {
+            let (u, v) = (x - 32.0, y - 32.0);
+            let along = c * u + s * v;
+            let across = -s * u + c * v;
+            4.0 * along + 0.2 * across * across
+        });
+        let angles = assign_orientations(&grid, 32, 32, 2.0);
+        assert_eq!(angles.len(), 1, "{:?}", angles);
+        assert!(angle_diff(angles[0], theta) < 0.02, "{:?}", angles);
+    }
+
+    #[test]
+    fn second_orientation_needs_eighty_percent_of_the_peak() {
+        // x gradients left of the keypoint, y gradients right of it
+        let two_edges = |right_slope: f32| {
+            grid_from(64, move |x, y| if x < 32.0 { 3.0 * x } else { 96.0 + right_slope * (y - 32.0) })
+        };
+        let angles = assign_orientations(&two_edges(3.0), 32, 32, 2.0);
+        assert_eq!(angles.len(), 2, "{:?}", angles);
+        assert!(angles.iter().any(|&a| angle_diff(a, 0.0) < 0.05), "{:?}", angles);
+        assert!(angles.iter().any(|&a| angle_diff(a, std::f32::consts::FRAC_PI_2) < 0.05), "{:?}", angles);
+
+        let angles = assign_orientations(&two_edges(1.5), 32, 32, 2.0);
+        assert_eq!(angles.len(), 1, "{:?}", angles);
+        assert!(angle_diff(angles[0], 0.0) < 0.05, "{:?}", angles);
+    }
+
+    #[test]
+    fn upsampled_octave_finds_small_blobs_in_image_coordinates() {
+        // a blob finer than octave 0's smallest detectable scale
+        let (w, h) = (64, 64);
+        let centre = (30.1, 20.3);
+        let image = blob_image(w, h, &[(centre.0, centre.1, 1.5)]);
+        let near = |kps: &[Keypoint]| -> Vec<Keypoint> {
+            kps.iter()
+                .filter(|kp| ((kp.x - centre.0).powi(2) + (kp.y - centre.1).powi(2)).sqrt() < 2.0)
+                .cloned()
+                .collect()
+        };
+
+        let mut config = SiftConfig::default();
+        let (_, plain) = crate::run_detect(&image, w, h, &config, None).unwrap();
+        assert!(near(&plain).is_empty());
+
+        config.set_upsample(true);
+        let (_, upsampled) = crate::run_detect(&image, w, h, &config, None).unwrap();
+        let found = near(&upsampled);
+        assert!(!found.is_empty());
+        for kp in &found {
+            assert_eq!(kp.octave, -1);
+            // position and scale are reported in input pixels, i.e. halved
+            assert!((kp.octave_x / 2.0 - kp.x).abs() < 1e-4 && (kp.octave_y / 2.0 - kp.y).abs() < 1e-4);
+            assert!(((kp.x - centre.0).powi(2) + (kp.y - centre.1).powi(2)).sqrt() < 0.1, "{:?}", kp);
+            assert!(kp.sigma > 1.0 && kp.sigma < 2.5, "{:?}", kp);
+        }
+    }
+
+    #[test]
+    fn retain_strongest_keeps_the_n_largest_responses_in_order() {
+        let responses = [0.2, 0.9, 0.5, 0.9, 0.1, 0.7, 0.5];
+        let mut kps: Vec<Keypoint> = responses
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| Keypoint::new(i as f32, 0.0, 2.0, 0.0, 0, 1, r))
+            .collect();
+        // the two 0.5 responses tie for the fourth place: the earlier one wins
+        retain_strongest(&mut kps, 4);
+        let kept: Vec<f32> = kps.iter().map(|kp| kp.x).collect();
+        assert_eq!(kept, vec![1.0, 2.0, 3.0, 5.0]);
+
+        retain_strongest(&mut kps, 10);
+        assert_eq!(kps.len(), 4);
+    }
+
+    #[test]
+    fn max_features_keeps_the_strongest_detections() {
+        let (w, h) = (128, 96);
+        let blobs: Vec<(f32, f32, f32)> = (0..12)
+            .map(|i| (16.0 + (i % 4) as f32 * 30.0, 18.0 + (i / 4) as f32 * 30.0, 2.5 + (i % 3) as f32 * 0.5))
+            .collect();
+        let image = blob_image(w, h, &blobs);
+        let mut config = SiftConfig::default();
+        let (_, all) = crate::run_detect(&image, w, h, &config, None).unwrap();
+        assert!(all.len() > 5);
+
+        config.set_max_features(Some(5));
+        let (_, limited) = crate::run_detect(&image, w, h, &config, None).unwrap();
+        let mut expected: Vec<f32> = all.iter().map(|kp| kp.response).collect();
+        expected.sort_by(|a, b| b.total_cmp(a));
+        let mut kept: Vec<f32> = limited.iter().map(|kp| kp.response).collect();
+        kept.sort_by(|a, b| b.total_cmp(a));
+        assert_eq!(kept, expected[..5]);
+    }
+
+    fn l2(v: &[f32]) -> f32 {
+        v.iter().map(|x| x * x).sum::<f32>().sqrt()
+    }
+
+    #[test]
+    fn descriptor_norms_give_unit_length() {
+        // one dominant bin, as produced by a single strong edge
+        let hist: Vec<f32> = (0..128).map(|i| if i == 5 { 40.0 } else { (i % 7) as f32 }).collect();
+
+        let mut clipped = hist.clone();
+        normalize_descriptor(&mut clipped, DescriptorNorm::ClippedL2, 0.2);
+        assert!((l2(&clipped) - 1.0).abs() < 1e-5);
+        // clipping shrinks the dominant bin relative to the rest
+        assert!(clipped[5] / clipped[6] < hist[5] / hist[6]);
+
+        let mut plain = hist.clone();
+        normalize_descriptor(&mut plain, DescriptorNorm::L2, 0.2);
+        assert!((l2(&plain) - 1.0).abs() < 1e-5);
+        assert!((plain[5] / plain[6] - hist[5] / hist[6]).abs() < 1e-4);
+
+        let mut root = hist.clone();
+        normalize_descriptor(&mut root, DescriptorNorm::RootSift, 0.2);
+        assert!((l2(&root) - 1.0).abs() < 1e-5);
+        let l1: f32 = hist.iter().sum();
+        assert!(root.iter().zip(&hist).all(|(r, h)| (r * r - h / l1).abs() < 1e-6));
+
+        // an empty window stays all zero instead of dividing by zero
+        for norm in [DescriptorNorm::ClippedL2, DescriptorNorm::L2, DescriptorNorm::RootSift] {
+            let mut zero = vec![0.0f32; 128];
+            normalize_descriptor(&mut zero, norm, 0.2);
+            assert!(zero.iter().all(|&v| v == 0.0));
+        }
+    }
+
+    #[test]
+    fn rootsift_descriptors_from_the_pipeline_have_unit_length() {
+        let (w, h) = (96, 96);
+        let image = blob_image(w, h, &[(30.0, 40.0, 3.0), (65.0, 60.0, 5.0)]);
+        let mut config = SiftConfig::default();
+        config.set_descriptor_norm(DescriptorNorm::RootSift);
+        let result = crate::run_sift(&image, w, h, &config, None).unwrap();
+        assert!(!result.kps.is_empty());
+        for desc in result.descriptors.chunks_exact(128) {
+            assert!((l2(desc) - 1.0).abs() < 1e-4);
+            assert!(desc.iter().all(|&v| v >= 0.0));
+        }
+    }
+}
@@ -1,15 +1,59 @@
+#[cfg(feature = "cli")]
+mod cli;
+mod colmap;
+mod config;
+#[cfg(feature = "decode")]
+mod decode;
+mod distance;
+mod distribution;
+mod epipolar;
+mod error;
+mod feature_io;
 mod gaussian_blur;
 mod grid;
+mod homography;
 mod interpolate;
+mod kdtree;
 mod keypoints;
+mod linalg;
+mod mask;
 mod match_keypoints;
 mod octaves;
+mod par;
+mod ransac;
 mod rgb_to_gray;
+mod rng;
+mod simd;
+#[cfg(all(feature = "parallel", target_arch = "wasm32"))]
+mod thread_pool;
 
+pub use crate::config::{
+    DescriptorNorm, KeypointDistribution, MatchAlgorithm, MatchConfig, RansacConfig, SiftConfig,
+};
+#[cfg(feature = "decode")]
+pub use crate::decode::GrayImage;
+pub use crate::error::SiftError;
+// entry point of the `sift` binary, not part of the library API
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub use crate::cli::main as cli_main;
+pub use crate::keypoints::{KeypointField, KeypointSet};
+#[cfg(all(feature = "parallel", target_arch = "wasm32"))]
+pub use crate::thread_pool::{init_thread_pool, wbg_rayon_start_worker, PoolBuilder};
+use crate::distribution::{grid_bucket, ssc};
+use crate::epipolar::{estimate_essential, estimate_fundamental, EpipolarModel};
 use crate::grid::Grid;
+use crate::homography::estimate_homography;
 use crate::interpolate::{bilinear_resize, calculate_resize_dimensions};
-use crate::keypoints::{detect_keypoints, extract_descriptors, flatten_keypoints};
-use crate::octaves::generate_pyramid;
+use crate::keypoints::{
+    detect_keypoints, extract_descriptors, flatten_keypoints, flatten_octave_coords,
+    keypoints_from_flat, retain_strongest, Keypoint, KEYPOINT_STRIDE,
+};
+use crate::mask::mask_pyramid;
+use crate::octaves::{generate_pyramid, Pyramid};
+use crate::ransac::matched_points;
+use js_sys::{Float32Array, Uint8Array};
+use std::cell::OnceCell;
 use wasm_bindgen::prelude::*;
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -30,6 +74,14 @@ impl ResizeResult {
     pub fn data(&self) -> Vec<u8> {
         self.data.clone()
     }
+    /// Zero-copy view of `data`, see `SiftResult::keypoints_view`.
+    pub fn data_view(&self) -> Uint8Array {
+        unsafe { Uint8Array::view(&self.data) }
+    }
+    /// Moves `data` out without cloning it; afterwards `data` is empty.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
     #[wasm_bindgen(getter)]
     pub fn width(&self) -> u32 {
         self.width
@@ -46,7 +98,10 @@ pub fn resize_image(
     original_width: u32,
     original_height: u32,
     target_long_edge: u32,
-) -> ResizeResult {
+) -> Result<ResizeResult, JsError> {
+    if target_long_edge == 0 {
+        return Err(SiftError::invalid_parameter("target_long_edge", "must be > 0").into());
+    }
     let (new_width, new_height) =
         calculate_resize_dimensions(original_width, original_height, target_long_edge);
 
@@ -56,75 +111,441 @@ pub fn resize_image(
         original_height,
         new_width,
         new_height,
-    );
+    )?;
 
-    ResizeResult {
+    Ok(ResizeResult {
         data: resized_data,
         width: new_width,
         height: new_height,
-    }
+    })
 }
 
 #[wasm_bindgen]
-pub fn rgba_to_gray(image_buffer: &[u8], width: u32, height: u32) -> Vec<u8> {
-    rgb_to_gray::rgba_to_gray(image_buffer, width as usize, height as usize)
+pub fn resize_image_buffer(
+    buffer: &ImageBuffer,
+    original_width: u32,
+    original_height: u32,
+    target_long_edge: u32,
+) -> Result<ResizeResult, JsError> {
+    resize_image(&buffer.data, original_width, original_height, target_long_edge)
+}
+
+#[wasm_bindgen]
+pub fn rgba_to_gray(image_buffer: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsError> {
+    Ok(rgb_to_gray::rgba_to_gray(image_buffer, width, height)?)
 }
 
 #[wasm_bindgen]
 pub struct SiftResult {
-    keypoints: Vec<f32>,   // [x, y, octave, level, sigma, angle, ...]
+    kps: Vec<Keypoint>,
     descriptors: Vec<f32>, // 128D per keypoint
+    // Flat forms of `kps`, built on first use by the views:
+    // [x, y, octave, level, sigma, angle, ...] in image space and
+    // [x, y, sigma, ...] in the keypoint's octave grid
+    keypoints: OnceCell<Vec<f32>>,
+    octave_keypoints: OnceCell<Vec<f32>>,
+    descriptors_u8: OnceCell<Vec<u8>>, // `descriptors` quantised on first use
+}
+
+impl SiftResult {
+    fn new(kps: Vec<Keypoint>, descriptors: Vec<f32>) -> Self {
+        SiftResult {
+            kps,
+            descriptors,
+            keypoints: OnceCell::new(),
+            octave_keypoints: OnceCell::new(),
+            descriptors_u8: OnceCell::new(),
+        }
+    }
+
+    fn flat_keypoints(&self) -> &[f32] {
+        self.keypoints.get_or_init(|| flatten_keypoints(&self.kps))
+    }
+
+    fn quantized_descriptors(&self) -> &[u8] {
+        self.descriptors_u8.get_or_init(|| keypoints::quantize_descriptors(&self.descriptors))
+    }
+}
+
+/// Number of f32 values per keypoint in `SiftResult::keypoints`; the field
+/// offsets are given by `KeypointField`.
+#[wasm_bindgen]
+pub fn keypoint_stride() -> usize {
+    KEYPOINT_STRIDE
 }
 
 #[wasm_bindgen]
 impl SiftResult {
     #[wasm_bindgen(getter)]
     pub fn keypoints(&self) -> Vec<f32> {
-        self.keypoints.clone()
+        match self.keypoints.get() {
+            Some(flat) => flat.clone(),
+            None => flatten_keypoints(&self.kps),
+        }
     }
     #[wasm_bindgen(getter)]
     pub fn descriptors(&self) -> Vec<f32> {
         self.descriptors.clone()
     }
+    #[wasm_bindgen(getter)]
+    pub fn octave_keypoints(&self) -> Vec<f32> {
+        match self.octave_keypoints.get() {
+            Some(flat) => flat.clone(),
+            None => flatten_octave_coords(&self.kps),
+        }
+    }
+    /// `descriptors` quantised to u8 (x512, saturated), a quarter of the size;
+    /// match them with `match_descriptors_topk_u8`. Quantised once and kept
+    /// with the result, like the flat arrays behind the views.
+    #[wasm_bindgen(getter)]
+    pub fn descriptors_u8(&self) -> Vec<u8> {
+        self.quantized_descriptors().to_vec()
+    }
+    /// The keypoints as struct-of-arrays, built on each call; keep the
+    /// returned set rather than calling this per column.
+    #[wasm_bindgen(getter)]
+    pub fn keypoint_set(&self) -> KeypointSet {
+        KeypointSet::from_keypoints(&self.kps)
+    }
+
+    /// Zero-copy view of `keypoints` into wasm memory.
+    ///
+    /// The view is only valid until the next allocation in the module (memory
+    /// growth detaches it) and must not outlive this result; copy it with
+    /// `slice()` if it has to be kept. The flat array is built on the first
+    /// call and kept with the result.
+    pub fn keypoints_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(self.flat_keypoints()) }
+    }
+    /// Zero-copy view of `descriptors`, see `keypoints_view`.
+    pub fn descriptors_view(&self) -> Float32Array {
+        unsafe { Float32Array::view(&self.descriptors) }
+    }
+    /// Zero-copy view of `descriptors_u8`, see `keypoints_view`.
+    pub fn descriptors_u8_view(&self) -> Uint8Array {
+        unsafe { Uint8Array::view(self.quantized_descriptors()) }
+    }
+    /// Zero-copy view of `octave_keypoints`, see `keypoints_view`.
+    pub fn octave_keypoints_view(&self) -> Float32Array {
+        let flat = self.octave_keypoints.get_or_init(|| flatten_octave_coords(&self.kps));
+        unsafe { Float32Array::view(flat) }
+    }
+
+    /// `keypoints`, moving out the array kept by `keypoints_view` instead of
+    /// cloning it.
+    pub fn take_keypoints(&mut self) -> Vec<f32> {
+        self.keypoints.take().unwrap_or_else(|| flatten_keypoints(&self.kps))
+    }
+    /// Moves `descriptors` out without cloning it; afterwards the field is empty,
+    /// and so is `descriptors_u8` unless it was read before.
+    pub fn take_descriptors(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.descriptors)
+    }
+    /// `octave_keypoints`, moving out the array kept by
+    /// `octave_keypoints_view` instead of cloning it.
+    pub fn take_octave_keypoints(&mut self) -> Vec<f32> {
+        self.octave_keypoints.take().unwrap_or_else(|| flatten_octave_coords(&self.kps))
+    }
+
+    /// Keypoints and descriptors in Lowe's `.key` text format (row, column,
+    /// scale, orientation, then the descriptor quantised like
+    /// `descriptors_u8`). Orientations and descriptor bins are converted to
+    /// Lowe's y-up convention: angles are negated into (-pi, pi] and the 8
+    /// orientation bins of every cell are reversed.
+    pub fn to_lowe_key(&self) -> Result<String, JsError> {
+        Ok(feature_io::write_lowe_key(&self.kps, &self.descriptors)?)
+    }
+    /// Keypoints in the `.frame` format of VLFeat's `sift` tool, one
+    /// `x y scale orientation` line each. VLFeat shares our angle convention
+    /// and descriptor layout, so nothing is converted.
+    pub fn to_vlfeat_frames(&self) -> Result<String, JsError> {
+        Ok(feature_io::write_vlfeat(&self.kps, &self.descriptors)?.0)
+    }
+    /// Descriptors in VLFeat's `.descr` format, one line of quantised values
+    /// per keypoint.
+    pub fn to_vlfeat_descriptors(&self) -> Result<String, JsError> {
+        Ok(feature_io::write_vlfeat(&self.kps, &self.descriptors)?.1)
+    }
+    /// Binary serialisation with a versioned header that keeps every keypoint
+    /// field and the f32 descriptors exactly; see `from_bytes`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, JsError> {
+        Ok(feature_io::write_binary(&self.kps, &self.descriptors)?)
+    }
+
+    /// Keypoints and descriptors as a COLMAP feature file (`<image name>.txt`
+    /// for `feature_importer`), with `x y scale orientation` per keypoint or,
+    /// with `affine`, the 6-column affine shape. Positions use COLMAP's
+    /// convention of (0.5, 0.5) at the centre of the top-left pixel.
+    pub fn to_colmap_features(&self, affine: bool) -> Result<String, JsError> {
+        Ok(colmap::write_features(&self.kps, &self.descriptors, affine)?)
+    }
+
+    /// Reads a Lowe `.key` file, converting orientations and descriptor bins
+    /// back from Lowe's convention. The format has no octave, level or response:
+    /// octave and level are derived from the scale with `config` as in
+    /// `describe`, and responses are 0.
+    pub fn from_lowe_key(text: &str, config: &SiftConfig) -> Result<SiftResult, JsError> {
+        let (kps, desc) = feature_io::read_lowe_key(text, config)?;
+        Ok(SiftResult::new(kps, desc))
+    }
+    /// Reads a VLFeat `.frame` / `.descr` pair, see `from_lowe_key`. An empty
+    /// `descriptors` gives a result without descriptors.
+    pub fn from_vlfeat(frames: &str, descriptors: &str, config: &SiftConfig) -> Result<SiftResult, JsError> {
+        let (kps, desc) = feature_io::read_vlfeat(frames, descriptors, config)?;
+        Ok(SiftResult::new(kps, desc))
+    }
+    /// Reads the output of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SiftResult, JsError> {
+        let (kps, desc) = feature_io::read_binary(bytes)?;
+        Ok(SiftResult::new(kps, desc))
+    }
+}
+
+/// Byte buffer that lives in wasm memory, so images can be written in place
+/// from JS and processed without copying them in on every call.
+///
+/// Fill it through `view()` (e.g. `buf.view().set(pixels)`), then pass it to
+/// `sift_image_buffer` or `resize_image_buffer`. As with the result views,
+/// re-acquire the view after any call that may allocate.
+#[wasm_bindgen]
+pub struct ImageBuffer {
+    data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl ImageBuffer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(len: usize) -> ImageBuffer {
+        ImageBuffer { data: vec![0; len] }
+    }
+    #[wasm_bindgen(getter)]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn view(&self) -> Uint8Array {
+        unsafe { Uint8Array::view(&self.data) }
+    }
+    /// Address of the first byte in wasm memory, for callers that build their
+    /// own views on `memory.buffer`.
+    pub fn ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+}
+
+#[wasm_bindgen]
+pub fn sift(image_buffer: &[u8], width: u32, height: u32, scales: usize) -> Result<SiftResult, JsError> {
+    let config = SiftConfig::default().with_scales(scales);
+    Ok(run_sift(image_buffer, width, height, &config, None)?)
+}
+
+#[wasm_bindgen]
+pub fn sift_with_config(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+) -> Result<SiftResult, JsError> {
+    Ok(run_sift(image_buffer, width, height, config, None)?)
+}
+
+/// Like `sift_with_config`, but skips extrema on zero pixels of `mask`, a u8
+/// image of the same size as the input (see also `mask_from_rects`).
+#[wasm_bindgen]
+pub fn sift_with_mask(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    mask: &[u8],
+    config: &SiftConfig,
+) -> Result<SiftResult, JsError> {
+    Ok(run_sift(image_buffer, width, height, config, Some(mask))?)
+}
+
+/// Builds a detection mask from [x, y, w, h, ...] rectangles. With `exclude`
+/// the rectangles are ignored regions (overlays, watermarks), otherwise they
+/// are the only regions searched.
+#[wasm_bindgen]
+pub fn mask_from_rects(width: u32, height: u32, rects: &[u32], exclude: bool) -> Result<Vec<u8>, JsError> {
+    Ok(mask::mask_from_rects(width, height, rects, exclude)?)
+}
+
+#[wasm_bindgen]
+pub fn sift_image_buffer(
+    buffer: &ImageBuffer,
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+) -> Result<SiftResult, JsError> {
+    Ok(run_sift(&buffer.data, width, height, config, None)?)
+}
+
+/// Decodes a PNG, JPEG or PGM file (detected from its leading bytes) to 8-bit
+/// grayscale, turned upright according to its EXIF orientation. 16-bit
+/// samples are reduced to 8 bits with rounding.
+#[cfg(feature = "decode")]
+#[wasm_bindgen]
+pub fn decode_gray(bytes: &[u8]) -> Result<GrayImage, JsError> {
+    Ok(decode::decode_gray(bytes)?)
+}
+
+/// `sift` on an encoded PNG, JPEG or PGM file instead of raw pixels. The
+/// keypoints refer to the upright image as returned by `decode_gray`, which
+/// for EXIF orientations 5 to 8 has width and height swapped.
+#[cfg(feature = "decode")]
+#[wasm_bindgen]
+pub fn sift_from_encoded(bytes: &[u8]) -> Result<SiftResult, JsError> {
+    Ok(run_sift_encoded(bytes, &SiftConfig::default())?)
+}
+
+#[cfg(feature = "decode")]
+#[wasm_bindgen]
+pub fn sift_from_encoded_with_config(bytes: &[u8], config: &SiftConfig) -> Result<SiftResult, JsError> {
+    Ok(run_sift_encoded(bytes, config)?)
+}
+
+/// Computes descriptors at caller-supplied keypoints, e.g. from another
+/// detector, a tracker or a previous frame.
+///
+/// `keypoints` uses the flat `SiftResult::keypoints` layout; only x, y, sigma
+/// and angle (image space, radians) are read, octave and level are derived
+/// from sigma, which may not exceed the larger image side. Returns 128
+/// floats per keypoint, in input order; keypoints off the image get an all-zero
+/// descriptor.
+#[wasm_bindgen]
+pub fn describe(image_buffer: &[u8], width: u32, height: u32, keypoints: &[f32]) -> Result<Vec<f32>, JsError> {
+    Ok(run_describe(image_buffer, width, height, keypoints, &SiftConfig::default(), true)?)
+}
+
+#[wasm_bindgen]
+pub fn describe_with_config(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    keypoints: &[f32],
+    config: &SiftConfig,
+) -> Result<Vec<f32>, JsError> {
+    Ok(run_describe(image_buffer, width, height, keypoints, config, true)?)
+}
+
+/// Keypoints only, without descriptors: the returned result has the usual
+/// keypoint fields but an empty `descriptors`. Descriptors for all or a
+/// filtered subset of the keypoints can be computed later with
+/// `compute_descriptors`.
+#[wasm_bindgen]
+pub fn detect(image_buffer: &[u8], width: u32, height: u32, config: &SiftConfig) -> Result<SiftResult, JsError> {
+    let (_, kps) = run_detect(image_buffer, width, height, config, None)?;
+    Ok(SiftResult::new(kps, Vec::new()))
 }
 
+/// Descriptors for keypoints returned by `detect` (or `sift`), possibly
+/// filtered. Unlike `describe`, the octave and level fields are used as-is,
+/// so the image and `config` must be the ones used for detection; the output
+/// then equals what `sift` computes for the same keypoints.
 #[wasm_bindgen]
-pub fn sift(image_buffer: &[u8], width: u32, height: u32, scales: usize) -> SiftResult {
+pub fn compute_descriptors(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    keypoints: &[f32],
+    config: &SiftConfig,
+) -> Result<Vec<f32>, JsError> {
+    Ok(run_describe(image_buffer, width, height, keypoints, config, false)?)
+}
+
+// Validates the config and builds the DoG and Gaussian pyramids of an 8-bit image
+fn build_pyramid(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+) -> Result<(Pyramid, Pyramid), SiftError> {
+    config.validate_for_image(width, height)?;
+
     // Convert input to f32
     let base_data: Vec<f32> = image_buffer.iter().map(|&v| v as f32).collect();
-    let base = Grid::new(&base_data, width, height);
+    let base = Grid::try_new(&base_data, width, height)?;
 
-    // Parameters
-    let sigma0 = 1.6_f32;
-    let sigma_n = 0.5_f32;
-    let contrast_thresh = 0.03_f32;
-    let edge_r = 10.0_f32;
-    let k = 2.0_f32.powf(1.0 / scales as f32);
+    generate_pyramid(
+        &base,
+        config.scales(),
+        config.sigma0(),
+        config.sigma_n(),
+        config.max_octaves(),
+        config.upsample(),
+    )
+}
 
-    // Build pyramid
-    let (dogs, gaussians) = generate_pyramid(&base, scales, sigma0, sigma_n, None);
+fn run_describe(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    keypoints: &[f32],
+    config: &SiftConfig,
+    derive_levels: bool,
+) -> Result<Vec<f32>, SiftError> {
+    let (_, gaussians) = build_pyramid(image_buffer, width, height, config)?;
+    let kps = keypoints_from_flat(keypoints, width, height, config, gaussians.len(), derive_levels)?;
+    Ok(extract_descriptors(&gaussians, &kps, config))
+}
 
-    // Detect keypoints
-    let kps = detect_keypoints(
-        &dogs,
-        &gaussians,
-        scales,
-        sigma0,
-        contrast_thresh,
-        edge_r,
-        k,
-    );
-
-    // Flat keypoints for JS
-    let kps_flat = flatten_keypoints(&kps);
+fn run_sift(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+    mask: Option<&[u8]>,
+) -> Result<SiftResult, SiftError> {
+    let (gaussians, kps) = run_detect(image_buffer, width, height, config, mask)?;
 
     // Descriptors aligned with kps
-    let desc = extract_descriptors(&gaussians, &kps);
+    let desc = extract_descriptors(&gaussians, &kps, config);
 
-    SiftResult {
-        keypoints: kps_flat,
-        descriptors: desc,
+    Ok(SiftResult::new(kps, desc))
+}
+
+#[cfg(feature = "decode")]
+fn run_sift_encoded(bytes: &[u8], config: &SiftConfig) -> Result<SiftResult, SiftError> {
+    let image = decode::decode_gray(bytes)?;
+    run_sift(&image.data, image.width, image.height, config, None)
+}
+
+// Builds the pyramid and returns it with the detected, filtered keypoints
+fn run_detect(
+    image_buffer: &[u8],
+    width: u32,
+    height: u32,
+    config: &SiftConfig,
+    mask: Option<&[u8]>,
+) -> Result<(Pyramid, Vec<Keypoint>), SiftError> {
+    let (dogs, gaussians) = build_pyramid(image_buffer, width, height, config)?;
+
+    // Resample the mask to every octave
+    let masks = match mask {
+        Some(m) => Some(mask_pyramid(m, width, height, dogs.len(), config.upsample())?),
+        None => None,
+    };
+
+    // Detect keypoints
+    let mut kps = detect_keypoints(&dogs, &gaussians, config, masks.as_deref());
+    match config.distribution() {
+        KeypointDistribution::All => {}
+        KeypointDistribution::Anms => ssc(&mut kps, config.anms_target(), width, height),
+        KeypointDistribution::Grid => grid_bucket(
+            &mut kps,
+            config.grid_cols(),
+            config.grid_rows(),
+            config.grid_cell_cap(),
+            width,
+            height,
+        )?,
     }
+    if let Some(max_features) = config.max_features() {
+        retain_strongest(&mut kps, max_features);
+    }
+    Ok((gaussians, kps))
 }
 
 #[wasm_bindgen]
@@ -135,6 +556,212 @@ pub fn match_descriptors_topk(
     ratio: f32,
     cross_check: bool,
     top_k: usize,
-) -> Vec<u32> {
-    crate::match_keypoints::match_descriptors_topk_impl(desc1, desc2, d, ratio, cross_check, top_k)
+) -> Result<Vec<u32>, JsError> {
+    Ok(crate::match_keypoints::match_descriptors_topk_impl(
+        desc1,
+        desc2,
+        d,
+        ratio,
+        cross_check,
+        top_k,
+        &MatchConfig::default(),
+    )?)
+}
+
+#[wasm_bindgen]
+pub fn match_descriptors_topk_with_config(
+    desc1: &[f32],
+    desc2: &[f32],
+    d: usize,
+    ratio: f32,
+    cross_check: bool,
+    top_k: usize,
+    config: &MatchConfig,
+) -> Result<Vec<u32>, JsError> {
+    Ok(crate::match_keypoints::match_descriptors_topk_impl(
+        desc1,
+        desc2,
+        d,
+        ratio,
+        cross_check,
+        top_k,
+        config,
+    )?)
+}
+
+/// Quantises f32 descriptors to u8 as `SiftResult::descriptors_u8` does.
+#[wasm_bindgen]
+pub fn quantize_descriptors(descriptors: &[f32]) -> Vec<u8> {
+    keypoints::quantize_descriptors(descriptors)
+}
+
+/// `match_descriptors_topk` on u8 descriptors, e.g. `descriptors_u8`. `d`
+/// may be at most 258: beyond that the squared distances, compared as f32,
+/// are no longer exact.
+#[wasm_bindgen]
+pub fn match_descriptors_topk_u8(
+    desc1: &[u8],
+    desc2: &[u8],
+    d: usize,
+    ratio: f32,
+    cross_check: bool,
+    top_k: usize,
+) -> Result<Vec<u32>, JsError> {
+    Ok(crate::match_keypoints::match_descriptors_topk_impl(
+        desc1,
+        desc2,
+        d,
+        ratio,
+        cross_check,
+        top_k,
+        &MatchConfig::default(),
+    )?)
+}
+
+#[wasm_bindgen]
+pub fn match_descriptors_topk_u8_with_config(
+    desc1: &[u8],
+    desc2: &[u8],
+    d: usize,
+    ratio: f32,
+    cross_check: bool,
+    top_k: usize,
+    config: &MatchConfig,
+) -> Result<Vec<u32>, JsError> {
+    Ok(crate::match_keypoints::match_descriptors_topk_impl(
+        desc1,
+        desc2,
+        d,
+        ratio,
+        cross_check,
+        top_k,
+        config,
+    )?)
+}
+
+#[wasm_bindgen]
+pub struct HomographyResult {
+    matrix: Vec<f64>,  // 3x3, row-major, maps image 1 points to image 2
+    inliers: Vec<u8>,  // 1 per inlier match, 0 per outlier, in match order
+    num_inliers: usize,
+}
+
+#[wasm_bindgen]
+impl HomographyResult {
+    #[wasm_bindgen(getter)]
+    pub fn matrix(&self) -> Vec<f64> {
+        self.matrix.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn inliers(&self) -> Vec<u8> {
+        self.inliers.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn num_inliers(&self) -> usize {
+        self.num_inliers
+    }
+}
+
+#[wasm_bindgen]
+pub fn find_homography(
+    keypoints1: &[f32],
+    keypoints2: &[f32],
+    matches: &[u32],
+    config: &RansacConfig,
+) -> Result<HomographyResult, JsError> {
+    let (src, dst) = matched_points(keypoints1, keypoints2, matches)?;
+    let homography = estimate_homography(&src, &dst, config)?;
+
+    Ok(HomographyResult {
+        matrix: crate::linalg::mat3_to_vec(&homography.matrix),
+        num_inliers: homography.inliers.iter().filter(|&&m| m).count(),
+        inliers: homography.inliers.iter().map(|&m| m as u8).collect(),
+    })
+}
+
+#[wasm_bindgen]
+pub struct EpipolarResult {
+    matrix: Vec<f64>,         // 3x3, row-major, q^T M p = 0 for p in image 1, q in image 2
+    inliers: Vec<u8>,         // 1 per inlier match, 0 per outlier, in match order
+    sampson_errors: Vec<f32>, // Sampson distance in pixels per match
+    num_inliers: usize,
+}
+
+#[wasm_bindgen]
+impl EpipolarResult {
+    #[wasm_bindgen(getter)]
+    pub fn matrix(&self) -> Vec<f64> {
+        self.matrix.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn inliers(&self) -> Vec<u8> {
+        self.inliers.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn sampson_errors(&self) -> Vec<f32> {
+        self.sampson_errors.clone()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn num_inliers(&self) -> usize {
+        self.num_inliers
+    }
+}
+
+impl From<EpipolarModel> for EpipolarResult {
+    fn from(model: EpipolarModel) -> Self {
+        EpipolarResult {
+            matrix: crate::linalg::mat3_to_vec(&model.matrix),
+            num_inliers: model.inliers.iter().filter(|&&m| m).count(),
+            inliers: model.inliers.iter().map(|&m| m as u8).collect(),
+            sampson_errors: model.sampson_errors,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn find_fundamental(
+    keypoints1: &[f32],
+    keypoints2: &[f32],
+    matches: &[u32],
+    config: &RansacConfig,
+) -> Result<EpipolarResult, JsError> {
+    let (src, dst) = matched_points(keypoints1, keypoints2, matches)?;
+    Ok(estimate_fundamental(&src, &dst, config)?.into())
+}
+
+fn intrinsics_from_slice(name: &'static str, values: &[f64]) -> Result<[f64; 4], SiftError> {
+    match values {
+        &[fx, fy, cx, cy] => Ok([fx, fy, cx, cy]),
+        _ => Err(SiftError::BufferLength {
+            name,
+            expected: 4,
+            actual: values.len(),
+        }),
+    }
+}
+
+#[wasm_bindgen]
+pub fn find_essential(
+    keypoints1: &[f32],
+    keypoints2: &[f32],
+    matches: &[u32],
+    intrinsics1: &[f64], // [fx, fy, cx, cy]
+    intrinsics2: &[f64], // [fx, fy, cx, cy]
+    config: &RansacConfig,
+) -> Result<EpipolarResult, JsError> {
+    let (src, dst) = matched_points(keypoints1, keypoints2, matches)?;
+    let k1 = intrinsics_from_slice("intrinsics1", intrinsics1)?;
+    let k2 = intrinsics_from_slice("intrinsics2", intrinsics2)?;
+    Ok(estimate_essential(&src, &dst, k1, k2, config)?.into())
+}
+
+/// One image pair for COLMAP's `matches_importer`: a line with both image
+/// names, the `i j` keypoint index pairs of `matches` (as returned by
+/// `match_descriptors_topk`) and a blank line. Blocks for several pairs can
+/// simply be concatenated. `inliers` (e.g. `HomographyResult::inliers`)
+/// keeps only the flagged matches; pass an empty array to keep all. Import
+/// with `--match_type inliers` to store them as verified two-view geometry.
+#[wasm_bindgen]
+pub fn colmap_matches(image1: &str, image2: &str, matches: &[u32], inliers: &[u8]) -> Result<String, JsError> {
+    Ok(colmap::write_match_block(image1, image2, matches, inliers)?)
 }
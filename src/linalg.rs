@@ -0,0 +1,449 @@
+// Minimal dense linear algebra for the geometry estimators. Everything runs in
+// f64 and on tiny matrices (at most 10x10), so plain row-major slices are enough.
+
+pub type Mat3 = [[f64; 3]; 3];
+
+pub fn mat3_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut out = [[0.0f64; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+pub fn mat3_transpose(a: &Mat3) -> Mat3 {
+    let mut out = [[0.0f64; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = a[j][i];
+        }
+    }
+    out
+}
+
+pub fn mat3_det(a: &Mat3) -> f64 {
+    a[0][0] * (a[1][1] * a[2][2] - a[1][2] * a[2][1])
+        - a[0][1] * (a[1][0] * a[2][2] - a[1][2] * a[2][0])
+        + a[0][2] * (a[1][0] * a[2][1] - a[1][1] * a[2][0])
+}
+
+pub fn mat3_inverse(a: &Mat3) -> Option<Mat3> {
+    let det = mat3_det(a);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let mut out = [[0.0f64; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            // cofactor of a[j][i] (adjugate is the transposed cofactor matrix)
+            let (r0, r1) = ((j + 1) % 3, (j + 2) % 3);
+            let (c0, c1) = ((i + 1) % 3, (i + 2) % 3);
+            *v = (a[r0][c0] * a[r1][c1] - a[r0][c1] * a[r1][c0]) * inv_det;
+        }
+    }
+    Some(out)
+}
+
+pub fn mat3_from_slice(v: &[f64]) -> Mat3 {
+    [[v[0], v[1], v[2]], [v[3], v[4], v[5]], [v[6], v[7], v[8]]]
+}
+
+pub fn mat3_to_vec(m: &Mat3) -> Vec<f64> {
+    m.iter().flat_map(|row| row.iter().copied()).collect()
+}
+
+// Gram matrix A^T A of a row-major (rows x cols) matrix
+pub fn gram(a: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+    let mut out = vec![0.0f64; cols * cols];
+    for r in 0..rows {
+        let row = &a[r * cols..(r + 1) * cols];
+        for i in 0..cols {
+            if row[i] == 0.0 {
+                continue;
+            }
+            for j in i..cols {
+                out[i * cols + j] += row[i] * row[j];
+            }
+        }
+    }
+    for i in 0..cols {
+        for j in 0..i {
+            out[i * cols + j] = out[j * cols + i];
+        }
+    }
+    out
+}
+
+// Eigen-decomposition of a symmetric n x n matrix with cyclic Jacobi rotations.
+// Returns eigenvalues in ascending order and the matching eigenvectors as rows.
+pub fn symmetric_eigen(m: &[f64], n: usize) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let mut a = m.to_vec();
+    let mut v = vec![0.0f64; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+
+    for _sweep in 0..64 {
+        let off: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        if off < 1e-30 {
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                let apq = a[p * n + q];
+                if apq.abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                // A <- J^T A J, touching only rows/columns p and q
+                for k in 0..n {
+                    let akp = a[k * n + p];
+                    let akq = a[k * n + q];
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let apk = a[p * n + k];
+                    let aqk = a[q * n + k];
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let vkp = v[k * n + p];
+                    let vkq = v[k * n + q];
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[i * n + i].total_cmp(&a[j * n + j]));
+    let values = order.iter().map(|&i| a[i * n + i]).collect();
+    let vectors = order
+        .iter()
+        .map(|&i| (0..n).map(|k| v[k * n + i]).collect())
+        .collect();
+    (values, vectors)
+}
+
+// Unit vector x minimising |A x| for a row-major (rows x cols) matrix A
+pub fn null_vector(a: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+    let (_, vectors) = symmetric_eigen(&gram(a, rows, cols), cols);
+    vectors.into_iter().next().unwrap_or_else(|| vec![0.0; cols])
+}
+
+// The `count` right singular vectors of A with the smallest singular values,
+// i.e. an orthonormal basis of its (numerical) null space
+pub fn null_space(a: &[f64], rows: usize, cols: usize, count: usize) -> Vec<Vec<f64>> {
+    let (_, vectors) = symmetric_eigen(&gram(a, rows, cols), cols);
+    vectors.into_iter().take(count).collect()
+}
+
+// Singular value decomposition of a 3x3 matrix via the eigen-decomposition of
+// A^T A. Returns (U, singular values descending, V) with A = U diag(S) V^T.
+pub fn svd3(a: &Mat3) -> (Mat3, [f64; 3], Mat3) {
+    let ata = mat3_mul(&mat3_transpose(a), a);
+    let flat = mat3_to_vec(&ata);
+    let (values, vectors) = symmetric_eigen(&flat, 3);
+
+    // descending order
+    let mut v = [[0.0f64; 3]; 3];
+    let mut s = [0.0f64; 3];
+    for (col, idx) in (0..3).rev().enumerate() {
+        s[col] = values[idx].max(0.0).sqrt();
+        for row in 0..3 {
+            v[row][col] = vectors[idx][row];
+        }
+    }
+    // keep V a proper rotation
+    if mat3_det(&v) < 0.0 {
+        for row in v.iter_mut() {
+            row[2] = -row[2];
+        }
+    }
+
+    // U columns = A v_i / s_i, completing degenerate columns with a cross product
+    let mut u = [[0.0f64; 3]; 3];
+    for col in 0..3 {
+        if s[col] > 1e-12 {
+            for row in 0..3 {
+                u[row][col] = (0..3).map(|k| a[row][k] * v[k][col]).sum::<f64>() / s[col];
+            }
+        }
+    }
+    if s[1] <= 1e-12 {
+        // rank <= 1: any unit vector orthogonal to u0 will do
+        let u0 = [u[0][0], u[1][0], u[2][0]];
+        let helper = if u0[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+        let c = normalize3(cross3(&u0, &helper));
+        for row in 0..3 {
+            u[row][1] = c[row];
+        }
+    }
+    if s[2] <= 1e-12 {
+        let c = cross3(&[u[0][0], u[1][0], u[2][0]], &[u[0][1], u[1][1], u[2][1]]);
+        for row in 0..3 {
+            u[row][2] = c[row];
+        }
+    }
+    (u, s, v)
+}
+
+pub fn cross3(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn normalize3(a: [f64; 3]) -> [f64; 3] {
+    let n = (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
+    if n > 0.0 {
+        [a[0] / n, a[1] / n, a[2] / n]
+    } else {
+        a
+    }
+}
+
+// Solves the n x n system A x = b in place by Gaussian elimination with partial
+// pivoting. Zero pivots are replaced by a tiny value so that nearly singular
+// systems (as used by inverse iteration) still produce a usable direction.
+pub fn solve_linear(a: &mut [f64], b: &mut [f64], n: usize) {
+    for col in 0..n {
+        let pivot_row = (col..n)
+            .max_by(|&i, &j| a[i * n + col].abs().total_cmp(&a[j * n + col].abs()))
+            .unwrap_or(col);
+        if pivot_row != col {
+            for k in 0..n {
+                a.swap(pivot_row * n + k, col * n + k);
+            }
+            b.swap(pivot_row, col);
+        }
+        if a[col * n + col].abs() < 1e-300 {
+            a[col * n + col] = 1e-300;
+        }
+        let pivot = a[col * n + col];
+        for row in (col + 1)..n {
+            let factor = a[row * n + col] / pivot;
+            if factor == 0.0 {
+                continue;
+            }
+            for k in col..n {
+                a[row * n + k] -= factor * a[col * n + k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    for row in (0..n).rev() {
+        let tail: f64 = ((row + 1)..n).map(|k| a[row * n + k] * b[k]).sum();
+        b[row] = (b[row] - tail) / a[row * n + row];
+    }
+}
+
+// Reduces a general n x n row-major matrix to upper Hessenberg form with
+// Householder reflections. Each step is a similarity transform, so the
+// eigenvalues are unchanged.
+fn hessenberg(m: &[f64], n: usize) -> Vec<f64> {
+    let mut a = m.to_vec();
+    for k in 0..n.saturating_sub(2) {
+        // reflector I - 2 v v^T / |v|^2 mapping column k below the subdiagonal
+        // onto the subdiagonal entry
+        let mut v: Vec<f64> = ((k + 1)..n).map(|i| a[i * n + k]).collect();
+        let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm == 0.0 {
+            continue;
+        }
+        v[0] += if v[0] > 0.0 { norm } else { -norm };
+        let v_sq = v.iter().map(|x| x * x).sum::<f64>();
+
+        // from the left: rows k+1.. of every column
+        for col in 0..n {
+            let dot: f64 = v.iter().enumerate().map(|(i, vi)| vi * a[(k + 1 + i) * n + col]).sum();
+            let f = 2.0 * dot / v_sq;
+            for (i, vi) in v.iter().enumerate() {
+                a[(k + 1 + i) * n + col] -= f * vi;
+            }
+        }
+        // from the right: columns k+1.. of every row
+        for row in a.chunks_exact_mut(n) {
+            let tail = &mut row[k + 1..];
+            let dot: f64 = tail.iter().zip(&v).map(|(x, vi)| x * vi).sum();
+            let f = 2.0 * dot / v_sq;
+            for (x, vi) in tail.iter_mut().zip(&v) {
+                *x -= f * vi;
+            }
+        }
+    }
+    a
+}
+
+// Characteristic polynomial det(A - t I) of an n x n row-major matrix, as
+// ascending coefficients. On the Hessenberg form H, expanding the leading
+// (k+1) x (k+1) minor along its last column gives
+// p[k+1] = (h[k][k] - t) p[k] - sum_i h[i][k] * (h[i+1][i] ... h[k][k-1]) * p[i].
+pub fn characteristic_polynomial(m: &[f64], n: usize) -> Vec<f64> {
+    let h = hessenberg(m, n);
+    let mut minors: Vec<Vec<f64>> = vec![vec![1.0]];
+    for k in 0..n {
+        let prev = &minors[k];
+        let mut next = vec![0.0f64; k + 2];
+        for (d, &c) in prev.iter().enumerate() {
+            next[d] += h[k * n + k] * c;
+            next[d + 1] -= c;
+        }
+        let mut subdiagonal = 1.0;
+        for i in (0..k).rev() {
+            subdiagonal *= h[(i + 1) * n + i];
+            let f = h[i * n + k] * subdiagonal;
+            if f == 0.0 {
+                continue;
+            }
+            for (d, &c) in minors[i].iter().enumerate() {
+                next[d] -= f * c;
+            }
+        }
+        minors.push(next);
+    }
+    minors.pop().unwrap_or_default()
+}
+
+fn horner(c: &[f64], t: f64) -> f64 {
+    c.iter().rev().fold(0.0, |acc, &v| acc * t + v)
+}
+
+// Real roots of the polynomial with ascending coefficients `c`, in increasing
+// order. The real roots of p' cut the line into pieces on which p is
+// monotone, so every piece whose ends differ in sign holds exactly one root,
+// which bisection pins down to full precision. Roots of even multiplicity are
+// only reported when p vanishes exactly at a critical point.
+pub fn real_roots(c: &[f64]) -> Vec<f64> {
+    let scale = c.iter().fold(0.0f64, |m, v| m.max(v.abs()));
+    let Some(degree) = c.iter().rposition(|v| v.abs() > 1e-14 * scale) else {
+        return Vec::new();
+    };
+    let c = &c[..=degree];
+    match degree {
+        0 => return Vec::new(),
+        1 => return vec![-c[0] / c[1]],
+        _ => {}
+    }
+
+    // Cauchy's bound: every root lies strictly inside (-bound, bound)
+    let lead = c[degree];
+    let bound = 1.0 + c[..degree].iter().fold(0.0f64, |m, v| m.max((v / lead).abs()));
+    let derivative: Vec<f64> = c.iter().enumerate().skip(1).map(|(d, v)| d as f64 * v).collect();
+    let mut knots = vec![-bound];
+    knots.extend(real_roots(&derivative).into_iter().map(|t| t.clamp(-bound, bound)));
+    knots.push(bound);
+
+    let mut roots = Vec::new();
+    for pair in knots.windows(2) {
+        let (mut lo, mut hi) = (pair[0], pair[1]);
+        let (f_lo, f_hi) = (horner(c, lo), horner(c, hi));
+        if f_lo == 0.0 {
+            roots.push(lo);
+            continue;
+        }
+        if f_lo.signum() == f_hi.signum() {
+            continue;
+        }
+        loop {
+            let mid = 0.5 * (lo + hi);
+            if mid <= lo || mid >= hi {
+                break;
+            }
+            if horner(c, mid).signum() == f_lo.signum() {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        roots.push(0.5 * (lo + hi));
+    }
+    roots.dedup_by(|a, b| (*a - *b).abs() <= 1e-12 * (1.0 + b.abs()));
+    roots
+}
+
+// Eigenvector of the n x n row-major matrix `m` for a known real eigenvalue,
+// by a few steps of inverse iteration
+pub fn eigenvector(m: &[f64], n: usize, lambda: f64) -> Vec<f64> {
+    let shift = lambda + 1e-10 * (1.0 + lambda.abs());
+    let mut v = vec![1.0f64; n];
+    for _ in 0..3 {
+        let mut a = m.to_vec();
+        for i in 0..n {
+            a[i * n + i] -= shift;
+        }
+        solve_linear(&mut a, &mut v, n);
+        let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if !(norm.is_finite() && norm > 0.0) {
+            break;
+        }
+        for x in v.iter_mut() {
+            *x /= norm;
+        }
+    }
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn real_roots_of_a_characteristic_polynomial() {
+        // similar to diag(-3, -0.5, 0.25, 2, 7) plus a rotation block with
+        // eigenvalues 1 +- 2i, which must not be reported
+        let n = 7;
+        let mut m = vec![0.0f64; n * n];
+        for (i, v) in [-3.0, -0.5, 0.25, 2.0, 7.0].into_iter().enumerate() {
+            m[i * n + i] = v;
+        }
+        m[5 * n + 5] = 1.0;
+        m[5 * n + 6] = -2.0;
+        m[6 * n + 5] = 2.0;
+        m[6 * n + 6] = 1.0;
+        // conjugate by an upper triangular matrix with unit diagonal
+        let mut t = vec![0.0f64; n * n];
+        let mut t_inv = vec![0.0f64; n * n];
+        for i in 0..n {
+            t[i * n + i] = 1.0;
+            t_inv[i * n + i] = 1.0;
+            if i + 1 < n {
+                t[i * n + i + 1] = 0.5;
+            }
+        }
+        // inverse of I + 0.5 N is sum_k (-0.5 N)^k
+        for i in 0..n {
+            for j in (i + 1)..n {
+                t_inv[i * n + j] = (-0.5f64).powi((j - i) as i32);
+            }
+        }
+        let mul = |a: &[f64], b: &[f64]| -> Vec<f64> {
+            (0..n * n)
+                .map(|ij| (0..n).map(|k| a[ij / n * n + k] * b[k * n + ij % n]).sum())
+                .collect()
+        };
+        let a = mul(&mul(&t, &m), &t_inv);
+
+        let roots = real_roots(&characteristic_polynomial(&a, n));
+        let expected = [-3.0, -0.5, 0.25, 2.0, 7.0];
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (r, e) in roots.iter().zip(expected) {
+            assert!((r - e).abs() < 1e-9, "{:?}", roots);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+// Detection masks: a u8 image of the input's size where zero pixels are
+// excluded from detection, resampled to every octave the same way
+// `generate_pyramid` resamples the image.
+
+use crate::error::{check_buffer_len, pixel_count, SiftError};
+use crate::grid::Grid;
+use crate::octaves::downsample_half;
+
+// One mask per octave, with the same dimensions as that octave's levels
+pub fn mask_pyramid(
+    mask: &[u8],
+    width: u32,
+    height: u32,
+    num_octaves: usize,
+    upsample: bool,
+) -> Result<Vec<Grid<u8>>, SiftError> {
+    check_buffer_len("mask", mask.len(), width, height, 1)?;
+    let base = Grid::new(mask, width, height);
+    let mut current = if upsample { upsample_nearest(&base) } else { base };
+
+    let mut out = Vec::with_capacity(num_octaves);
+    for _ in 0..num_octaves {
+        let next = downsample_half(&current);
+        out.push(current);
+        current = next;
+    }
+    Ok(out)
+}
+
+// x2 nearest-neighbour upsampling, matching the doubled first octave where
+// pixel (2x, 2y) corresponds to input pixel (x, y)
+fn upsample_nearest(src: &Grid<u8>) -> Grid<u8> {
+    let new_w = src.get_width() * 2;
+    let new_h = src.get_height() * 2;
+    let mut data = Vec::with_capacity(new_w as usize * new_h as usize);
+    for y in 0..new_h {
+        for x in 0..new_w {
+            data.push(src.get_pixel(x / 2, y / 2));
+        }
+    }
+    Grid {
+        width: new_w,
+        height: new_h,
+        data,
+    }
+}
+
+// Builds a mask from [x, y, w, h, ...] rectangles, clipped to the image.
+// With `exclude` the rectangles are masked out of an otherwise open image,
+// otherwise they are the only regions left open.
+pub fn mask_from_rects(
+    width: u32,
+    height: u32,
+    rects: &[u32],
+    exclude: bool,
+) -> Result<Vec<u8>, SiftError> {
+    let len = pixel_count(width, height)?;
+    if !rects.len().is_multiple_of(4) {
+        return Err(SiftError::invalid_parameter("rects", "length must be a multiple of 4 (x, y, w, h)"));
+    }
+    let (outside, inside) = if exclude { (255u8, 0u8) } else { (0u8, 255u8) };
+    let mut mask = vec![outside; len];
+    for r in rects.chunks_exact(4) {
+        let x0 = r[0].min(width) as usize;
+        let y0 = r[1].min(height) as usize;
+        let x1 = r[0].saturating_add(r[2]).min(width) as usize;
+        let y1 = r[1].saturating_add(r[3]).min(height) as usize;
+        for y in y0..y1 {
+            let row = y * width as usize;
+            mask[row + x0..row + x1].fill(inside);
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiftConfig;
+
+    #[test]
+    fn rects_are_clipped_and_follow_the_exclude_flag() {
+        let mask = mask_from_rects(6, 4, &[4, 1, 10, 2], true).unwrap();
+        let open: Vec<usize> = (0..mask.len()).filter(|&i| mask[i] == 0).collect();
+        assert_eq!(open, vec![10, 11, 16, 17]);
+
+        let mask = mask_from_rects(6, 4, &[4, 1, 10, 2], false).unwrap();
+        assert_eq!(mask.iter().filter(|&&v| v != 0).count(), 4);
+        assert!(mask_from_rects(6, 4, &[0, 0, 1], true).is_err());
+    }
+
+    #[test]
+    fn pyramid_levels_match_the_octave_sizes() {
+        let mask = mask_from_rects(40, 24, &[0, 0, 10, 24], true).unwrap();
+        let masks = mask_pyramid(&mask, 40, 24, 3, true).unwrap();
+        let sizes: Vec<(u32, u32)> = masks.iter().map(|m| (m.get_width(), m.get_height())).collect();
+        assert_eq!(sizes, vec![(80, 48), (40, 24), (20, 12)]);
+        // octave -1 pixel (2x, 2y) is input pixel (x, y)
+        assert_eq!(masks[0].get_pixel(19, 10), 0);
+        assert_eq!(masks[0].get_pixel(20, 10), 255);
+    }
+
+    #[test]
+    fn masked_region_yields_no_keypoints() {
+        let (w, h) = (96u32, 96u32);
+        let blobs = [(28.0f32, 30.0f32), (66.0, 30.0), (28.0, 68.0), (66.0, 68.0)];
+        let image: Vec<u8> = (0..w * h)
+            .map(|i| {
+                let (x, y) = ((i % w) as f32, (i / w) as f32);
+                let darkness: f32 = blobs
+                    .iter()
+                    .map(|&(bx, by)| (-((x - bx).powi(2) + (y - by).powi(2)) / (2.0 * 2.5 * 2.5)).exp())
+                    .sum();
+                (220.0 - 180.0 * darkness.min(1.0)).round() as u8
+            })
+            .collect();
+        let rect = [10u32, 12, 36, 36];
+        let inside = |x: f32, y: f32| {
+            x >= rect[0] as f32 && y >= rect[1] as f32 && x < (rect[0] + rect[2]) as f32 && y < (rect[1] + rect[3]) as f32
+        };
+        let config = SiftConfig::default();
+
+        let (_, open) = crate::run_detect(&image, w, h, &config, None).unwrap();
+        assert!(open.iter().any(|kp| inside(kp.x(), kp.y())));
+
+        let mask = mask_from_rects(w, h, &rect, true).unwrap();
+        let (_, masked) = crate::run_detect(&image, w, h, &config, Some(&mask)).unwrap();
+        assert!(!masked.is_empty());
+        assert!(masked.iter().all(|kp| !inside(kp.x(), kp.y())));
+    }
+}
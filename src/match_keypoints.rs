@@ -1,104 +1,145 @@
-fn l2_sq(a: &[f32], b: &[f32]) -> f32 {
-    // Calculates squared L2 distance between two vectors
-    // Returns sum of squared differences between corresponding elements
-    let mut acc = 0.0f32;
-    for i in 0..a.len() {
-        let d = a[i] - b[i];
-        acc += d * d;
-    }
-    acc
+use crate::config::{MatchAlgorithm, MatchConfig};
+use crate::distance::{best_two_batch, DescriptorValue};
+use crate::error::SiftError;
+use crate::kdtree::KdForest;
+use crate::par;
+
+// queries per parallel task on the kd-forest, each task owning one searcher
+const FOREST_QUERY_BLOCK: usize = 64;
+
+// Nearest-neighbour lookup over one descriptor set, brute force or kd-forest
+enum NnIndex<'a, T> {
+    Linear { set: &'a [T], d: usize },
+    Forest { forest: KdForest<'a, T>, d: usize, checks: usize },
 }
 
-fn best_two_in_set(query: &[f32], set: &[f32], d: usize) -> Option<(usize, f32, f32)> {
-    // Finds the two closest vectors to query in set based on L2 distance
-    // Returns index of best match along with best and second best distances
-    if d == 0 { return None; }
-    let n = set.len() / d;
-    if n == 0 { return None; }
-
-    let mut best_idx = usize::MAX;
-    let mut best = f32::INFINITY;
-    let mut second = f32::INFINITY;
-
-    for j in 0..n {
-        let start = j * d;
-        let cand = &set[start..start + d];
-        let dist = l2_sq(query, cand);
-        if dist < best {
-            second = best;
-            best = dist;
-            best_idx = j;
-        } else if dist < second {
-            second = dist;
+impl<'a, T: DescriptorValue> NnIndex<'a, T> {
+    fn new(set: &'a [T], d: usize, config: &MatchConfig) -> Self {
+        match config.algorithm() {
+            MatchAlgorithm::BruteForce => NnIndex::Linear { set, d },
+            MatchAlgorithm::KdForest => {
+                let forest = KdForest::build(set, d, config.trees(), config.seed() as u64);
+                NnIndex::Forest { forest, d, checks: config.checks() }
+            }
         }
     }
-    if best_idx == usize::MAX { None } else { Some((best_idx, best, second)) }
-}
 
-fn best_index_in_set(query: &[f32], set: &[f32], d: usize) -> Option<usize> {
-    // Finds index of closest vector to query in set based on L2 distance
-    // Returns index of best match if set is not empty
-    if d == 0 { return None; }
-    let n = set.len() / d;
-    if n == 0 { return None; }
-
-    let mut best_idx = 0usize;
-    let mut best = f32::INFINITY;
-    for j in 0..n {
-        let start = j * d;
-        let cand = &set[start..start + d];
-        let dist = l2_sq(query, cand);
-        if dist < best {
-            best = dist;
-            best_idx = j;
+    fn best_two_all(&self, queries: &[T]) -> Vec<Option<(usize, f32, f32)>> {
+        // Finds the two closest vectors in the set for every row of `queries`
+        // Returns index of best match along with best and second best distances
+        match self {
+            NnIndex::Linear { set, d } => best_two_batch(queries, set, *d),
+            NnIndex::Forest { forest, d, checks } => {
+                let blocks = par::map_chunks(queries, FOREST_QUERY_BLOCK * d, |_, block| {
+                    let mut searcher = forest.searcher();
+                    block
+                        .chunks_exact(*d)
+                        .map(|q| forest.best_two(q, *checks, &mut searcher))
+                        .collect::<Vec<_>>()
+                });
+                blocks.into_iter().flatten().collect()
+            }
         }
     }
-    Some(best_idx)
 }
 
-pub fn match_descriptors_with_scores(desc1: &[f32], desc2: &[f32], d: usize, ratio: f32, cross_check: bool) -> Vec<f32> {
+fn check_descriptors<T>(name: &'static str, desc: &[T], d: usize) -> Result<(), SiftError> {
+    if !desc.len().is_multiple_of(d) {
+        return Err(SiftError::BufferLength {
+            name,
+            expected: desc.len() / d * d,
+            actual: desc.len(),
+        });
+    }
+    Ok(())
+}
+
+pub fn match_descriptors_with_scores<T: DescriptorValue>(
+    desc1: &[T],
+    desc2: &[T],
+    d: usize,
+    ratio: f32,
+    cross_check: bool,
+    config: &MatchConfig,
+) -> Result<Vec<f32>, SiftError> {
     // Matches descriptors between two sets using ratio test and optional cross checking
     // Returns vector of matched indices and distances as [i1,j1,dist1, i2,j2,dist2, ...]
-    assert!(d > 0, "descriptor dimension must be > 0");
-    assert_eq!(desc1.len() % d, 0, "desc1 length must be a multiple of d");
-    assert_eq!(desc2.len() % d, 0, "desc2 length must be a multiple of d");
+    if d == 0 {
+        return Err(SiftError::invalid_parameter("d", "descriptor dimension must be > 0"));
+    }
+    if d > T::MAX_DIM {
+        return Err(SiftError::invalid_parameter(
+            "d",
+            format!("distances of u8 descriptors are only exact for d <= {}, got {}", T::MAX_DIM, d),
+        ));
+    }
+    if ratio.is_nan() || ratio <= 0.0 {
+        return Err(SiftError::invalid_parameter("ratio", format!("must be > 0, got {}", ratio)));
+    }
+    config.validate()?;
+    check_descriptors("desc1", desc1, d)?;
+    check_descriptors("desc2", desc2, d)?;
 
     let n1 = desc1.len() / d;
     let n2 = desc2.len() / d;
     if n1 == 0 || n2 == 0 {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
     let mut out: Vec<f32> = Vec::with_capacity(n1 * 3);
+    let index2 = NnIndex::new(desc2, d, config);
+    // the reverse index is only needed for cross checking
+    let index1 = if cross_check { Some(NnIndex::new(desc1, d, config)) } else { None };
+
+    // ratio test on the forward matches, all queries in one batch
+    let candidates: Vec<(usize, usize, f32)> = index2
+        .best_two_all(desc1)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, nn)| {
+            let (j, best, second) = nn?;
+            let pass = second.is_finite() && best.is_finite() && second > 0.0 && (best / second) < ratio;
+            pass.then_some((i, j, best))
+        })
+        .collect();
+
+    // cross check: the match must also be the best one back from desc2
+    let reverse = match &index1 {
+        Some(index1) => {
+            let queries: Vec<T> = candidates
+                .iter()
+                .flat_map(|&(_, j, _)| desc2[j * d..(j + 1) * d].iter().copied())
+                .collect();
+            Some(index1.best_two_all(&queries))
+        }
+        None => None,
+    };
 
-    for i in 0..n1 {
-        let q = &desc1[i * d..(i + 1) * d];
-        if let Some((j, best, second)) = best_two_in_set(q, desc2, d) {
-            if second.is_finite() && best.is_finite() && second > 0.0 && (best / second) < ratio {
-                if cross_check {
-                    let q2 = &desc2[j * d..(j + 1) * d];
-                    if let Some(bi) = best_index_in_set(q2, desc1, d) {
-                        if bi == i {
-                            out.push(i as f32);
-                            out.push(j as f32);
-                            out.push(best);
-                        }
-                    }
-                } else {
-                    out.push(i as f32);
-                    out.push(j as f32);
-                    out.push(best);
-                }
+    for (k, &(i, j, best)) in candidates.iter().enumerate() {
+        if let Some(reverse) = &reverse {
+            if reverse[k].map(|(bi, _, _)| bi) != Some(i) {
+                continue;
             }
         }
+        out.push(i as f32);
+        out.push(j as f32);
+        out.push(best);
     }
-    out
+    Ok(out)
 }
 
-pub fn match_descriptors_topk_impl(desc1: &[f32], desc2: &[f32], d: usize, ratio: f32, cross_check: bool, top_k: usize) -> Vec<u32> {
+pub fn match_descriptors_topk_impl<T: DescriptorValue>(
+    desc1: &[T],
+    desc2: &[T],
+    d: usize,
+    ratio: f32,
+    cross_check: bool,
+    top_k: usize,
+    config: &MatchConfig,
+) -> Result<Vec<u32>, SiftError> {
     // Finds top-k matches between descriptor sets based on distance
     // Returns vector of matched indices [i1,j1, i2,j2, ...] for top k matches
-    let scored = match_descriptors_with_scores(desc1, desc2, d, ratio, cross_check);
+    let scored = match_descriptors_with_scores(desc1, desc2, d, ratio, cross_check, config)?;
     let mut triples: Vec<(u32, u32, f32)> = scored
         .chunks_exact(3)
         .map(|c| (c[0] as u32, c[1] as u32, c[2]))
@@ -112,5 +153,54 @@ pub fn match_descriptors_topk_impl(desc1: &[f32], desc2: &[f32], d: usize, ratio
         out.push(i);
         out.push(j);
     }
-    out
-}
\ No newline at end of file
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn kd_forest_with_unlimited_checks_equals_brute_force() {
+        let d = 128;
+        let mut rng = Rng::new(4);
+        let mut unit = || rng.unit_f32();
+        let desc2: Vec<f32> = (0..300 * d).map(|_| unit()).collect();
+        // perturbed copies of every other descriptor of desc2, then unrelated ones
+        let mut desc1: Vec<f32> = desc2
+            .chunks_exact(d)
+            .step_by(2)
+            .flat_map(|row| row.iter().map(|v| v + 0.05 * unit()).collect::<Vec<_>>())
+            .collect();
+        desc1.extend((0..50 * d).map(|_| unit()));
+
+        let brute = MatchConfig::default();
+        let mut forest = MatchConfig::default();
+        forest.set_algorithm(MatchAlgorithm::KdForest);
+        forest.set_checks(usize::MAX);
+        for cross_check in [false, true] {
+            let expected = match_descriptors_with_scores(&desc1, &desc2, d, 0.8, cross_check, &brute).unwrap();
+            let found = match_descriptors_with_scores(&desc1, &desc2, d, 0.8, cross_check, &forest).unwrap();
+            assert_eq!(found, expected);
+            assert!(expected.len() / 3 >= 150);
+        }
+    }
+
+    #[test]
+    fn u8_descriptors_longer_than_exact_distances_allow_are_rejected() {
+        let config = MatchConfig::default();
+        let max = <u8 as DescriptorValue>::MAX_DIM;
+        let a = vec![0u8; 2 * max];
+        let mut b = vec![255u8; 2 * max];
+        b[..max].fill(0);
+        let matches = match_descriptors_with_scores(&a[..max], &b, max, 0.8, false, &config).unwrap();
+        assert_eq!(matches, vec![0.0, 0.0, 0.0]);
+
+        let long = vec![0u8; max + 1];
+        assert!(matches!(
+            match_descriptors_with_scores(&long, &long, max + 1, 0.8, false, &config),
+            Err(SiftError::InvalidParameter { .. })
+        ));
+    }
+}
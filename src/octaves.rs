@@ -1,7 +1,13 @@
 use crate::gaussian_blur::{gaussian_blur, kernel_size_for_sigma};
+use crate::error::SiftError;
 use crate::grid::Grid;
+use crate::par;
 
-fn downsample_half(src: &Grid<f32>) -> Grid<f32> {
+// the levels of one octave, and one such Vec per octave
+pub type Octave = Vec<Grid<f32>>;
+pub type Pyramid = Vec<Octave>;
+
+pub(crate) fn downsample_half<T: Copy>(src: &Grid<T>) -> Grid<T> {
     let new_w = (src.get_width() / 2).max(1);
     let new_h = (src.get_height() / 2).max(1);
     let mut dst_buffer = Vec::with_capacity(new_w as usize * new_h as usize);
@@ -21,15 +27,57 @@ fn downsample_half(src: &Grid<f32>) -> Grid<f32> {
 
 }
 
+fn upsample_double(src: &Grid<f32>) -> Grid<f32> {
+    // bilinear x2 upsampling; destination pixel (2x, 2y) lands exactly on source pixel (x, y)
+    let src_w = src.get_width();
+    let src_h = src.get_height();
+    let new_w = src_w * 2;
+    let new_h = src_h * 2;
+    let mut dst_buffer = Vec::with_capacity(new_w as usize * new_h as usize);
+    for y in 0..new_h {
+        let sy = y as f32 * 0.5;
+        let y0 = sy.floor() as u32;
+        let y1 = (y0 + 1).min(src_h - 1);
+        let fy = sy - y0 as f32;
+        for x in 0..new_w {
+            let sx = x as f32 * 0.5;
+            let x0 = sx.floor() as u32;
+            let x1 = (x0 + 1).min(src_w - 1);
+            let fx = sx - x0 as f32;
+
+            let top = src.get_pixel(x0, y0) * (1.0 - fx) + src.get_pixel(x1, y0) * fx;
+            let bottom = src.get_pixel(x0, y1) * (1.0 - fx) + src.get_pixel(x1, y1) * fx;
+            dst_buffer.push(top * (1.0 - fy) + bottom * fy);
+        }
+    }
+    Grid {
+        width: new_w,
+        height: new_h,
+        data: dst_buffer,
+    }
+}
+
+// Index of the first octave: -1 when the input is upsampled x2, 0 otherwise
+pub fn first_octave(upsample: bool) -> i32 {
+    if upsample { -1 } else { 0 }
+}
+
 pub fn generate_octave(
     image_grid: &Grid<f32>,
     scales: usize,
     sigma0: f32,
     sigma_n: f32,
     k: f32,
-) -> (Vec<Grid<f32>>, Vec<Grid<f32>>) {
-    assert!(scales >= 1, "scales must be >= 1");
-    assert!(k > 1.0, "k must be > 1.0");
+) -> Result<(Octave, Octave), SiftError> {
+    if scales < 1 {
+        return Err(SiftError::invalid_parameter("scales", "must be >= 1"));
+    }
+    if k.is_nan() || k <= 1.0 {
+        return Err(SiftError::invalid_parameter("k", "must be > 1.0"));
+    }
+    if sigma0.is_nan() || sigma0 <= 0.0 {
+        return Err(SiftError::invalid_parameter("sigma0", "must be > 0"));
+    }
 
     let sigma_base = 0.0_f32.max(sigma0.powf(2.0) - sigma_n.powf(2.0)).sqrt();
     let mut gaussian_blurs: Vec<Grid<f32>> = Vec::with_capacity(scales + 3);
@@ -57,16 +105,11 @@ pub fn generate_octave(
         gaussian_blurs.push(next);
     }
 
-    // Build DoG images: G[i+1] - G[i]
-    let mut dogs: Vec<Grid<f32>> = Vec::with_capacity(scales + 2);
-    for i in 0..(scales + 2) {
-        let prev = &gaussian_blurs[i];
-        let next = &gaussian_blurs[i + 1];
-        let diff = next.difference(prev);
-        dogs.push(diff);
-    }
+    // Build DoG images: G[i+1] - G[i], one level per task
+    let levels: Vec<usize> = (0..(scales + 2)).collect();
+    let dogs: Vec<Grid<f32>> = par::map_slice(&levels, |&i| gaussian_blurs[i + 1].difference(&gaussian_blurs[i]));
 
-    (dogs, gaussian_blurs)
+    Ok((dogs, gaussian_blurs))
 }
 
 pub fn generate_pyramid(
@@ -75,15 +118,23 @@ pub fn generate_pyramid(
     sigma0: f32,
     sigma_n: f32,
     max_octaves: Option<usize>,
-) -> (Vec<Vec<Grid<f32>>>, Vec<Vec<Grid<f32>>>) {
-    assert!(scales >= 1, "scales must be >= 1");
+    upsample: bool,
+) -> Result<(Pyramid, Pyramid), SiftError> {
+    if scales < 1 {
+        return Err(SiftError::invalid_parameter("scales", "must be >= 1"));
+    }
     let k = 2.0_f32.powf(1.0 / scales as f32);
 
-    let mut dog_vec:Vec<Vec<Grid<f32>>> = vec![];
-    let mut gaussian_vec:Vec<Vec<Grid<f32>>> = vec![];
+    let mut dog_vec: Pyramid = vec![];
+    let mut gaussian_vec: Pyramid = vec![];
 
-    let mut current_base = Grid::new(base.get_buffer(), base.get_width(), base.get_height());
-    let mut current_sigma_n = sigma_n;
+    // With `upsample` the pyramid starts at octave -1: the input is doubled in
+    // size, which also doubles its assumed blur when measured in the new pixels
+    let (mut current_base, mut current_sigma_n) = if upsample {
+        (upsample_double(base), 2.0 * sigma_n)
+    } else {
+        (Grid::new(base.get_buffer(), base.get_width(), base.get_height()), sigma_n)
+    };
 
     let mut octave_idx = 0usize;
     loop {
@@ -93,7 +144,7 @@ pub fn generate_pyramid(
         let min_dim = current_base.get_width().min(current_base.get_height());
         if min_dim < 16 { break; }
 
-        let (dogs, gaussians) = generate_octave(&current_base, scales, sigma0, current_sigma_n, k);
+        let (dogs, gaussians) = generate_octave(&current_base, scales, sigma0, current_sigma_n, k)?;
         dog_vec.push(dogs);
         gaussian_vec.push(gaussians);
 
@@ -105,5 +156,5 @@ pub fn generate_pyramid(
         octave_idx += 1;
     }
 
-    (dog_vec, gaussian_vec)
+    Ok((dog_vec, gaussian_vec))
 }
\ No newline at end of file
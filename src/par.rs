@@ -0,0 +1,121 @@
+// Data-parallel helpers behind the `parallel` feature. With the feature they
+// run on rayon's global pool, otherwise they are plain sequential loops; the
+// callers are written once against these. Every helper keeps results in input
+// order and each item is computed exactly as in the sequential loop, so the
+// output does not depend on the thread count.
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// f applied to every item, results in input order
+#[cfg(feature = "parallel")]
+pub fn map_slice<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync + Send,
+{
+    items.par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn map_slice<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    F: Fn(&T) -> R,
+{
+    items.iter().map(f).collect()
+}
+
+// f applied to every `chunk`-long piece of `items` (the last may be shorter)
+// with the piece's index, results in input order
+#[cfg(feature = "parallel")]
+pub fn map_chunks<T, R, F>(items: &[T], chunk: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(usize, &[T]) -> R + Sync + Send,
+{
+    items.par_chunks(chunk).enumerate().map(|(i, c)| f(i, c)).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn map_chunks<T, R, F>(items: &[T], chunk: usize, f: F) -> Vec<R>
+where
+    F: Fn(usize, &[T]) -> R,
+{
+    items.chunks(chunk).enumerate().map(|(i, c)| f(i, c)).collect()
+}
+
+// f called on every `chunk`-long mutable piece of `data` with the piece's index
+#[cfg(feature = "parallel")]
+pub fn for_each_chunk_mut<T, F>(data: &mut [T], chunk: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync + Send,
+{
+    data.par_chunks_mut(chunk).enumerate().for_each(|(i, c)| f(i, c));
+}
+
+#[cfg(not(feature = "parallel"))]
+pub fn for_each_chunk_mut<T, F>(data: &mut [T], chunk: usize, f: F)
+where
+    F: Fn(usize, &mut [T]),
+{
+    data.chunks_mut(chunk).enumerate().for_each(|(i, c)| f(i, c));
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod tests {
+    use crate::config::{MatchAlgorithm, MatchConfig, SiftConfig};
+    use crate::match_keypoints::match_descriptors_with_scores;
+    use crate::rng::Rng;
+    use crate::run_sift;
+
+    // Gray image of overlapping random rectangles, plenty of corners and blobs
+    fn rectangles(width: usize, height: usize, seed: u64) -> Vec<u8> {
+        let mut rng = Rng::new(seed);
+        let mut image = vec![128u8; width * height];
+        for _ in 0..60 {
+            let (x0, y0) = (rng.below(width), rng.below(height));
+            let (w, h) = (8 + rng.below(60), 8 + rng.below(60));
+            let value = rng.below(256) as u8;
+            for y in y0..(y0 + h).min(height) {
+                image[y * width + x0..y * width + (x0 + w).min(width)].fill(value);
+            }
+        }
+        image
+    }
+
+    // Runs `f` on a dedicated rayon pool of `threads` threads
+    fn on_threads<R: Send>(threads: usize, f: impl FnOnce() -> R + Send) -> R {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(f)
+    }
+
+    #[test]
+    fn output_does_not_depend_on_the_thread_count() {
+        let (width, height) = (320, 240);
+        let a = rectangles(width, height, 1);
+        let b = rectangles(width, height, 2);
+        let config = SiftConfig::default();
+        let mut forest = MatchConfig::default();
+        forest.set_algorithm(MatchAlgorithm::KdForest);
+
+        let run = || {
+            let ra = run_sift(&a, width as u32, height as u32, &config, None).unwrap();
+            let rb = run_sift(&b, width as u32, height as u32, &config, None).unwrap();
+            let brute = match_descriptors_with_scores(&ra.descriptors, &rb.descriptors, 128, 0.8, true, &MatchConfig::default());
+            let approx = match_descriptors_with_scores(&ra.descriptors, &rb.descriptors, 128, 0.8, true, &forest);
+            (ra.keypoints(), ra.descriptors, rb.keypoints(), brute.unwrap(), approx.unwrap())
+        };
+        let sequential = on_threads(1, run);
+        assert!(sequential.0.len() / 6 >= 100, "{} keypoints", sequential.0.len() / 6);
+        assert!(sequential.3.len() / 3 >= 10, "{} matches", sequential.3.len() / 3);
+        for threads in [2, 4, 7] {
+            assert!(on_threads(threads, run) == sequential, "{} threads differ", threads);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Shared pieces of the RANSAC estimators: the sampling loop itself, point
+// normalisation and collecting matched positions.
+
+use crate::config::RansacConfig;
+use crate::error::SiftError;
+use crate::keypoints::KEYPOINT_STRIDE;
+use crate::rng::Rng;
+
+// Rounds of least-squares refitting on the inlier set after sampling
+const REFIT_ROUNDS: usize = 3;
+
+pub type Points = Vec<[f64; 2]>;
+
+// Number of iterations needed to draw at least one all-inlier sample of
+// `sample_size` with probability `confidence`, given the current inlier ratio
+fn adaptive_iterations(
+    confidence: f64,
+    inlier_ratio: f64,
+    sample_size: usize,
+    max_iterations: usize,
+) -> usize {
+    let good_sample = inlier_ratio.powi(sample_size as i32);
+    if good_sample <= f64::EPSILON {
+        return max_iterations;
+    }
+    if good_sample >= 1.0 - f64::EPSILON {
+        return 1;
+    }
+    let n = (1.0 - confidence).ln() / (1.0 - good_sample).ln();
+    if !n.is_finite() {
+        return max_iterations;
+    }
+    (n.ceil().max(1.0) as usize).min(max_iterations)
+}
+
+// Best model found by `run` and the correspondences that support it
+pub struct Consensus<M> {
+    pub model: M,
+    pub inliers: Vec<bool>,
+}
+
+// Checks that src and dst pair up and that there are enough of them for one
+// minimal sample
+pub fn check_correspondences(
+    src: &[[f64; 2]],
+    dst: &[[f64; 2]],
+    sample_size: usize,
+) -> Result<(), SiftError> {
+    if src.len() != dst.len() {
+        return Err(SiftError::BufferLength {
+            name: "dst points",
+            expected: src.len(),
+            actual: dst.len(),
+        });
+    }
+    if src.len() < sample_size {
+        return Err(SiftError::invalid_parameter(
+            "matches",
+            format!("need at least {} matches, got {}", sample_size, src.len()),
+        ));
+    }
+    Ok(())
+}
+
+// RANSAC over `n` correspondences. `solve` maps a minimal sample (or, with
+// `refit`, the whole inlier set) to zero or more candidate models and
+// `error_sq` gives the squared pixel residual of correspondence `i` under a
+// model. The best sample is refitted on its inliers for a few rounds, keeping
+// each refit only if its support does not drop.
+pub fn run<M>(
+    n: usize,
+    sample_size: usize,
+    config: &RansacConfig,
+    solve: impl Fn(&[usize], bool) -> Vec<M>,
+    error_sq: impl Fn(&M, usize) -> f64,
+) -> Result<Consensus<M>, SiftError> {
+    let thresh_sq = (config.reproj_threshold() as f64).powi(2);
+    let max_iterations = config.max_iterations();
+    let mut rng = Rng::new(config.seed() as u64);
+    let score = |model: &M, mask: &mut [bool]| {
+        let mut count = 0;
+        for (i, m) in mask.iter_mut().enumerate() {
+            *m = error_sq(model, i) < thresh_sq;
+            count += *m as usize;
+        }
+        count
+    };
+
+    let mut best: Option<M> = None;
+    let mut best_count = 0usize;
+    let mut best_mask = vec![false; n];
+    let mut mask = vec![false; n];
+    let mut sample = Vec::with_capacity(sample_size);
+
+    let mut iterations = max_iterations;
+    let mut iter = 0;
+    while iter < iterations {
+        iter += 1;
+        rng.sample(n, sample_size, &mut sample);
+        for model in solve(&sample, false) {
+            let count = score(&model, &mut mask);
+            if count > best_count {
+                best_count = count;
+                best = Some(model);
+                std::mem::swap(&mut best_mask, &mut mask);
+                iterations = adaptive_iterations(
+                    config.confidence() as f64,
+                    count as f64 / n as f64,
+                    sample_size,
+                    max_iterations,
+                );
+            }
+        }
+    }
+
+    let mut model = match best {
+        Some(best) if best_count >= sample_size => best,
+        _ => {
+            return Err(SiftError::Estimation {
+                reason: format!("no model is supported by at least {} matches", sample_size),
+            })
+        }
+    };
+
+    for _ in 0..REFIT_ROUNDS {
+        let inlier_idx: Vec<usize> = (0..n).filter(|&i| best_mask[i]).collect();
+        let Some(refit) = solve(&inlier_idx, true).into_iter().next() else {
+            break;
+        };
+        let count = score(&refit, &mut mask);
+        if count < best_count {
+            break;
+        }
+        let unchanged = mask == best_mask;
+        model = refit;
+        best_count = count;
+        std::mem::swap(&mut best_mask, &mut mask);
+        if unchanged {
+            break;
+        }
+    }
+
+    Ok(Consensus {
+        model,
+        inliers: best_mask,
+    })
+}
+
+// Hartley normalisation: translate the centroid to the origin and scale so the
+// mean distance from it is sqrt(2). Returns the normalised points and the 3x3
+// similarity that maps original to normalised coordinates.
+pub fn normalize_points(points: &[[f64; 2]]) -> (Vec<[f64; 2]>, [[f64; 3]; 3]) {
+    let n = points.len().max(1) as f64;
+    let cx = points.iter().map(|p| p[0]).sum::<f64>() / n;
+    let cy = points.iter().map(|p| p[1]).sum::<f64>() / n;
+    let mean_dist = points
+        .iter()
+        .map(|p| ((p[0] - cx).powi(2) + (p[1] - cy).powi(2)).sqrt())
+        .sum::<f64>()
+        / n;
+    let s = if mean_dist > 1e-12 {
+        std::f64::consts::SQRT_2 / mean_dist
+    } else {
+        1.0
+    };
+    let normalized = points
+        .iter()
+        .map(|p| [(p[0] - cx) * s, (p[1] - cy) * s])
+        .collect();
+    let t = [[s, 0.0, -s * cx], [0.0, s, -s * cy], [0.0, 0.0, 1.0]];
+    (normalized, t)
+}
+
+// Collects the image-space positions of matched keypoints. `keypoints1` and
+// `keypoints2` use the flat `SiftResult::keypoints` layout and `matches` the
+// [i1, j1, i2, j2, ...] pairs from `match_descriptors_topk`.
+pub fn matched_points(
+    keypoints1: &[f32],
+    keypoints2: &[f32],
+    matches: &[u32],
+) -> Result<(Points, Points), SiftError> {
+    for (name, kps) in [("keypoints1", keypoints1), ("keypoints2", keypoints2)] {
+        if !kps.len().is_multiple_of(KEYPOINT_STRIDE) {
+            return Err(SiftError::BufferLength {
+                name,
+                expected: kps.len() / KEYPOINT_STRIDE * KEYPOINT_STRIDE,
+                actual: kps.len(),
+            });
+        }
+    }
+    if !matches.len().is_multiple_of(2) {
+        return Err(SiftError::invalid_parameter("matches", "length must be even (index pairs)"));
+    }
+    let n1 = keypoints1.len() / KEYPOINT_STRIDE;
+    let n2 = keypoints2.len() / KEYPOINT_STRIDE;
+
+    let mut src = Vec::with_capacity(matches.len() / 2);
+    let mut dst = Vec::with_capacity(matches.len() / 2);
+    for pair in matches.chunks_exact(2) {
+        let (i, j) = (pair[0] as usize, pair[1] as usize);
+        if i >= n1 || j >= n2 {
+            return Err(SiftError::invalid_parameter(
+                "matches",
+                format!("pair ({}, {}) is out of range for {} / {} keypoints", i, j, n1, n2),
+            ));
+        }
+        let a = &keypoints1[i * KEYPOINT_STRIDE..];
+        let b = &keypoints2[j * KEYPOINT_STRIDE..];
+        src.push([a[0] as f64, a[1] as f64]);
+        dst.push([b[0] as f64, b[1] as f64]);
+    }
+    Ok((src, dst))
+}
@@ -1,13 +1,17 @@
-pub fn rgba_to_gray(img_data: &[u8], width: usize, height: usize) -> Vec<u8> {
-    let mut gray = Vec::with_capacity(width * height);
+use crate::error::{check_buffer_len, SiftError};
+
+pub fn rgba_to_gray(img_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, SiftError> {
+    check_buffer_len("rgba buffer", img_data.len(), width, height, 4)?;
+    let mut gray = Vec::with_capacity(img_data.len() / 4);
     
     for chunk in img_data.chunks_exact(4) {
-        let r = chunk[0] as f32;
-        let g = chunk[1] as f32;
-        let b = chunk[2] as f32;
-        let gray_val = (0.299 * r + 0.587 * g + 0.114 * b).round() as u8;
-        gray.push(gray_val);
+        gray.push(luma(chunk[0], chunk[1], chunk[2]));
     }
     
-    gray
+    Ok(gray)
+}
+
+// Rec. 601 luma of one 8-bit RGB pixel
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8
 }
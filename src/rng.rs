@@ -0,0 +1,48 @@
+// Small seeded PRNG shared by RANSAC sampling, kd-forest construction and the
+// tests. Results must be reproducible run to run, so no OS entropy.
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    // splitmix64
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // k distinct indices in 0..n (k is tiny, so rejection is cheap)
+    pub fn sample(&mut self, n: usize, k: usize, out: &mut Vec<usize>) {
+        out.clear();
+        while out.len() < k {
+            let idx = self.below(n);
+            if !out.contains(&idx) {
+                out.push(idx);
+            }
+        }
+    }
+
+    // uniform in [0, 1) from the top 24 bits, i.e. every value exact in f32
+    #[cfg(test)]
+    pub fn unit_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // uniform in [lo, hi) with 53 random bits
+    #[cfg(test)]
+    pub fn uniform(&mut self, lo: f64, hi: f64) -> f64 {
+        lo + (hi - lo) * (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,220 @@
+// Small vector kernels shared by the hot loops. The wasm build enables
+// `simd128` in .cargo/config.toml; every other target gets the scalar loop,
+// which LLVM is free to auto-vectorise.
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub fn axpy(acc: &mut [f32], src: &[f32], w: f32) {
+    // acc[i] += w * src[i], four lanes at a time
+    use core::arch::wasm32::*;
+
+    let n = acc.len().min(src.len());
+    let lanes = n / 4 * 4;
+    let wv = f32x4_splat(w);
+    let mut i = 0;
+    while i < lanes {
+        // SAFETY: i + 4 <= n, and v128_load/v128_store have no alignment requirement
+        unsafe {
+            let a = v128_load(acc.as_ptr().add(i) as *const v128);
+            let s = v128_load(src.as_ptr().add(i) as *const v128);
+            v128_store(acc.as_mut_ptr().add(i) as *mut v128, f32x4_add(a, f32x4_mul(s, wv)));
+        }
+        i += 4;
+    }
+    for j in lanes..n {
+        acc[j] += w * src[j];
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+pub fn axpy(acc: &mut [f32], src: &[f32], w: f32) {
+    // acc[i] += w * src[i]
+    for (a, &s) in acc.iter_mut().zip(src) {
+        *a += w * s;
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub fn l2_sq_u8(a: &[u8], b: &[u8]) -> u32 {
+    // sum of (a[i] - b[i])^2 over u8 vectors, sixteen lanes at a time: |a - b|
+    // from two saturating subtractions, widened to i16 and squared-and-summed
+    // pairwise by i32x4_dot_i16x8
+    use core::arch::wasm32::*;
+
+    let n = a.len().min(b.len());
+    let lanes = n / 16 * 16;
+    let mut acc = i32x4_splat(0);
+    let mut i = 0;
+    while i < lanes {
+        // SAFETY: i + 16 <= n, and v128_load has no alignment requirement
+        unsafe {
+            let va = v128_load(a.as_ptr().add(i) as *const v128);
+            let vb = v128_load(b.as_ptr().add(i) as *const v128);
+            let diff = v128_or(u8x16_sub_sat(va, vb), u8x16_sub_sat(vb, va));
+            let lo = u16x8_extend_low_u8x16(diff);
+            let hi = u16x8_extend_high_u8x16(diff);
+            acc = i32x4_add(acc, i32x4_dot_i16x8(lo, lo));
+            acc = i32x4_add(acc, i32x4_dot_i16x8(hi, hi));
+        }
+        i += 16;
+    }
+    let mut sum = i32x4_extract_lane::<0>(acc) as u32
+        + i32x4_extract_lane::<1>(acc) as u32
+        + i32x4_extract_lane::<2>(acc) as u32
+        + i32x4_extract_lane::<3>(acc) as u32;
+    for j in lanes..n {
+        let d = a[j] as i32 - b[j] as i32;
+        sum += (d * d) as u32;
+    }
+    sum
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+pub fn l2_sq_u8(a: &[u8], b: &[u8]) -> u32 {
+    // sum of (a[i] - b[i])^2 over u8 vectors
+    a.iter()
+        .zip(b)
+        .map(|(&x, &y)| {
+            let d = x as i32 - y as i32;
+            (d * d) as u32
+        })
+        .sum()
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub fn l2_sq_f32_x4(q: &[f32], c: [&[f32]; 4]) -> [f32; 4] {
+    // squared distances from one query to four candidates; every query chunk
+    // is loaded once and reused for the four candidates
+    use core::arch::wasm32::*;
+
+    let n = c.iter().fold(q.len(), |n, s| n.min(s.len()));
+    let lanes = n / 4 * 4;
+    let mut acc = [f32x4_splat(0.0); 4];
+    let mut i = 0;
+    while i < lanes {
+        // SAFETY: i + 4 <= n for the query and every candidate, and v128_load
+        // has no alignment requirement
+        unsafe {
+            let qv = v128_load(q.as_ptr().add(i) as *const v128);
+            for k in 0..4 {
+                let cv = v128_load(c[k].as_ptr().add(i) as *const v128);
+                let d = f32x4_sub(qv, cv);
+                acc[k] = f32x4_add(acc[k], f32x4_mul(d, d));
+            }
+        }
+        i += 4;
+    }
+    let mut out = [0.0f32; 4];
+    for k in 0..4 {
+        out[k] = f32x4_extract_lane::<0>(acc[k])
+            + f32x4_extract_lane::<1>(acc[k])
+            + f32x4_extract_lane::<2>(acc[k])
+            + f32x4_extract_lane::<3>(acc[k]);
+        for j in lanes..n {
+            let d = q[j] - c[k][j];
+            out[k] += d * d;
+        }
+    }
+    out
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+pub fn l2_sq_f32_x4(q: &[f32], c: [&[f32]; 4]) -> [f32; 4] {
+    // squared distances from one query to four candidates
+    c.map(|cand| l2_sq_f32(q, cand))
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub fn l2_sq_f32(a: &[f32], b: &[f32]) -> f32 {
+    // sum of (a[i] - b[i])^2, four lanes at a time
+    use core::arch::wasm32::*;
+
+    let n = a.len().min(b.len());
+    let lanes = n / 4 * 4;
+    let mut acc = f32x4_splat(0.0);
+    let mut i = 0;
+    while i < lanes {
+        // SAFETY: i + 4 <= n, and v128_load has no alignment requirement
+        unsafe {
+            let d = f32x4_sub(
+                v128_load(a.as_ptr().add(i) as *const v128),
+                v128_load(b.as_ptr().add(i) as *const v128),
+            );
+            acc = f32x4_add(acc, f32x4_mul(d, d));
+        }
+        i += 4;
+    }
+    let mut sum = f32x4_extract_lane::<0>(acc)
+        + f32x4_extract_lane::<1>(acc)
+        + f32x4_extract_lane::<2>(acc)
+        + f32x4_extract_lane::<3>(acc);
+    for j in lanes..n {
+        let d = a[j] - b[j];
+        sum += d * d;
+    }
+    sum
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+pub fn l2_sq_f32(a: &[f32], b: &[f32]) -> f32 {
+    // sum of (a[i] - b[i])^2; eight independent partial sums so the loop can
+    // be vectorised without reassociating a single accumulator
+    let mut acc = [0.0f32; 8];
+    let chunks = a.len().min(b.len()) / 8 * 8;
+    for (ca, cb) in a[..chunks].chunks_exact(8).zip(b[..chunks].chunks_exact(8)) {
+        for k in 0..8 {
+            let d = ca[k] - cb[k];
+            acc[k] += d * d;
+        }
+    }
+    let mut sum = acc.iter().sum::<f32>();
+    for (x, y) in a[chunks..].iter().zip(&b[chunks..]) {
+        let d = x - y;
+        sum += d * d;
+    }
+    sum
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+pub fn l2_sq_u8_x4(q: &[u8], c: [&[u8]; 4]) -> [u32; 4] {
+    // u8 counterpart of l2_sq_f32_x4, with the l2_sq_u8 lane arithmetic
+    use core::arch::wasm32::*;
+
+    let n = c.iter().fold(q.len(), |n, s| n.min(s.len()));
+    let lanes = n / 16 * 16;
+    let mut acc = [i32x4_splat(0); 4];
+    let mut i = 0;
+    while i < lanes {
+        // SAFETY: i + 16 <= n for the query and every candidate, and v128_load
+        // has no alignment requirement
+        unsafe {
+            let qv = v128_load(q.as_ptr().add(i) as *const v128);
+            for k in 0..4 {
+                let cv = v128_load(c[k].as_ptr().add(i) as *const v128);
+                let diff = v128_or(u8x16_sub_sat(qv, cv), u8x16_sub_sat(cv, qv));
+                let lo = u16x8_extend_low_u8x16(diff);
+                let hi = u16x8_extend_high_u8x16(diff);
+                acc[k] = i32x4_add(acc[k], i32x4_dot_i16x8(lo, lo));
+                acc[k] = i32x4_add(acc[k], i32x4_dot_i16x8(hi, hi));
+            }
+        }
+        i += 16;
+    }
+    let mut out = [0u32; 4];
+    for k in 0..4 {
+        out[k] = i32x4_extract_lane::<0>(acc[k]) as u32
+            + i32x4_extract_lane::<1>(acc[k]) as u32
+            + i32x4_extract_lane::<2>(acc[k]) as u32
+            + i32x4_extract_lane::<3>(acc[k]) as u32;
+        for j in lanes..n {
+            let d = q[j] as i32 - c[k][j] as i32;
+            out[k] += (d * d) as u32;
+        }
+    }
+    out
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+pub fn l2_sq_u8_x4(q: &[u8], c: [&[u8]; 4]) -> [u32; 4] {
+    // squared distances from one query to four candidates
+    c.map(|cand| l2_sq_u8(q, cand))
+}
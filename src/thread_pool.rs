@@ -0,0 +1,103 @@
+// Web Worker backed rayon pool for the `parallel` feature on wasm, in the
+// style of wasm-bindgen-rayon. `initThreadPool(n)` starts n workers that
+// instantiate this module on the same shared memory; each worker blocks in
+// `wbg_rayon_start_worker` until rayon hands it a thread to run, which
+// happens when `PoolBuilder::build` installs the global pool. rayon's global
+// pool can only be installed once, so only the first `initThreadPool` call
+// starts workers; later ones are rejected before spawning anything.
+
+use rayon::ThreadBuilder;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(module = "/src/workerHelpers.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = startWorkers)]
+    fn start_workers(module: JsValue, memory: JsValue, builder: PoolBuilder) -> js_sys::Promise;
+}
+
+type ThreadReceiver = Mutex<mpsc::Receiver<ThreadBuilder>>;
+
+// set by the `initThreadPool` call that gets to start workers, and cleared
+// again if starting them fails so that the call can be retried
+static POOL_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[wasm_bindgen]
+pub struct PoolBuilder {
+    num_threads: usize,
+    sender: mpsc::Sender<ThreadBuilder>,
+    // leaked so that workers can keep a plain pointer to it
+    receiver: &'static ThreadReceiver,
+}
+
+#[wasm_bindgen]
+impl PoolBuilder {
+    fn new(num_threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        PoolBuilder {
+            num_threads,
+            sender,
+            receiver: Box::leak(Box::new(Mutex::new(receiver))),
+        }
+    }
+
+    #[wasm_bindgen(js_name = numThreads)]
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    pub fn receiver(&self) -> usize {
+        self.receiver as *const ThreadReceiver as usize
+    }
+
+    // called by startWorkers once every worker is waiting for its thread
+    pub fn build(&mut self) {
+        let sender = self.sender.clone();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_threads)
+            .spawn_handler(move |thread| {
+                sender.send(thread).unwrap_throw();
+                Ok(())
+            })
+            .build_global()
+            .unwrap_throw();
+    }
+}
+
+/// Starts `num_threads` Web Workers and installs them as the global rayon
+/// pool. Call it exactly once and await the returned promise before the first
+/// `sift` call; any further call returns a rejected promise and starts no
+/// workers. If starting the workers fails, the promise is rejected with that
+/// error and the call may be retried.
+#[wasm_bindgen(js_name = initThreadPool)]
+pub fn init_thread_pool(num_threads: usize) -> js_sys::Promise {
+    let reject = |message: &str| js_sys::Promise::reject(&JsError::new(message).into());
+    if num_threads == 0 {
+        return reject("initThreadPool: num_threads must be > 0");
+    }
+    if POOL_STARTED.swap(true, Ordering::SeqCst) {
+        return reject("initThreadPool: the thread pool is already initialised");
+    }
+    let started = start_workers(wasm_bindgen::module(), wasm_bindgen::memory(), PoolBuilder::new(num_threads));
+    // startWorkers is async, so every failure arrives as a rejection: release
+    // the flag and pass the error on
+    let on_error = Closure::once(|error: JsValue| {
+        POOL_STARTED.store(false, Ordering::SeqCst);
+        wasm_bindgen::throw_val(error)
+    });
+    let promise = started.catch(&on_error);
+    // one small closure per attempt to start the pool
+    on_error.forget();
+    promise
+}
+
+// Worker entry point: runs the rayon thread handed over through `receiver`
+#[doc(hidden)]
+#[wasm_bindgen]
+pub fn wbg_rayon_start_worker(receiver: usize) {
+    // SAFETY: `receiver` comes from PoolBuilder::receiver, a leaked 'static
+    let receiver = unsafe { &*(receiver as *const ThreadReceiver) };
+    let thread = receiver.lock().unwrap_throw().recv().unwrap_throw();
+    thread.run();
+}
@@ -0,0 +1,49 @@
+// Worker glue for the `parallel` feature's thread pool (see thread_pool.rs).
+// The same file runs on the main thread, where it exports `startWorkers`, and
+// as the module of every pool worker, where it initialises the wasm module on
+// the shared memory and parks in `wbg_rayon_start_worker`.
+
+function waitForMsgType(target, type) {
+  return new Promise((resolve) => {
+    target.addEventListener('message', function onMsg({ data }) {
+      if (data == null || data.type !== type) return;
+      target.removeEventListener('message', onMsg);
+      resolve(data);
+    });
+  });
+}
+
+const WORKER_NAME = 'sift_rayon_worker';
+
+if (typeof self !== 'undefined' && self.name === WORKER_NAME) {
+  waitForMsgType(self, 'sift_worker_init').then(async ({ init, receiver }) => {
+    // the generated package entry point, three levels up from snippets/<crate>/src
+    const pkg = await import('../../..');
+    await pkg.default(init);
+    postMessage({ type: 'sift_worker_ready' });
+    pkg.wbg_rayon_start_worker(receiver);
+  });
+}
+
+let workers;
+
+export async function startWorkers(module, memory, builder) {
+  const n = builder.numThreads();
+  const init = {
+    type: 'sift_worker_init',
+    init: { module_or_path: module, memory },
+    receiver: builder.receiver(),
+  };
+  workers = await Promise.all(
+    Array.from({ length: n }, async () => {
+      const worker = new Worker(new URL('./workerHelpers.js', import.meta.url), {
+        type: 'module',
+        name: WORKER_NAME,
+      });
+      worker.postMessage(init);
+      await waitForMsgType(worker, 'sift_worker_ready');
+      return worker;
+    })
+  );
+  builder.build();
+}